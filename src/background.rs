@@ -0,0 +1,37 @@
+use crate::prelude::*;
+
+/// Messages posted to the main loop by work that runs off of the main thread. The main loop owns
+/// the `Client` and all document state, so background tasks report back through this channel
+/// rather than touching that state directly.
+pub enum BackgroundMessage {
+    /// A linter process has exited and its output has been parsed.
+    LinterFinished(LinterOutput),
+    /// A workspace-scoped linter process has exited and its output has been parsed.
+    WorkspaceLinterFinished(WorkspaceLinterOutput),
+    /// The debounce window of a linter has elapsed for the given version of a document.
+    DebounceElapsed {
+        uri: Uri,
+        version: DocumentVersion,
+        linter_index: usize,
+    },
+}
+
+pub struct LinterOutput {
+    pub job_id: JobId,
+    pub pid: Pid,
+    pub linter_name: String,
+    pub run_on: PicklsRunOn,
+    pub max_linter_count: usize,
+    pub uri: Uri,
+    pub version: DocumentVersion,
+    pub diagnostics: Result<Vec<Diagnostic>>,
+}
+
+pub struct WorkspaceLinterOutput {
+    pub job_id: WorkspaceJobId,
+    pub pid: Pid,
+    /// The document that triggered the run.
+    pub uri: Uri,
+    /// The diagnostics of every file that the linter reported on.
+    pub diagnostics: Result<HashMap<Uri, Vec<Diagnostic>>>,
+}
@@ -0,0 +1,190 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::watch;
+
+use crate::prelude::*;
+
+/// The JSON-RPC error code of responses to requests that the client cancelled.
+pub const REQUEST_CANCELLED: i64 = -32800;
+
+/// The error that requests fail with once they are cancelled.
+#[derive(Debug)]
+pub struct RequestCancelled;
+
+impl std::fmt::Display for RequestCancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("request cancelled")
+    }
+}
+
+impl std::error::Error for RequestCancelled {}
+
+/// Lets a request be abandoned while the main loop is busy handling it. Cancellation arrives on the
+/// thread that reads stdin, which kills the processes that the request is waiting on and wakes up
+/// the futures that are waiting for it.
+#[derive(Clone)]
+pub struct CancellationToken {
+    state: Arc<CancellationState>,
+}
+
+struct CancellationState {
+    cancelled: watch::Sender<bool>,
+    /// The process groups that the request is waiting on.
+    pids: Mutex<Vec<Pid>>,
+    /// The progress that the request reports, which clients may offer to cancel.
+    progress_token: Mutex<Option<ProgressToken>>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(CancellationState {
+                cancelled: watch::Sender::new(false),
+                pids: Default::default(),
+                progress_token: Default::default(),
+            }),
+        }
+    }
+}
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.state.cancelled.send_replace(true);
+        for &pid in self.state.pids.lock().unwrap().iter() {
+            kill_process_group(pid);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.state.cancelled.borrow()
+    }
+
+    /// Fails with `RequestCancelled` if the request was cancelled.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(RequestCancelled.into());
+        }
+        Ok(())
+    }
+
+    /// Completes once the request is cancelled.
+    pub async fn cancelled(&self) {
+        let mut cancelled = self.state.cancelled.subscribe();
+        // The sender lives as long as `self`, so this only returns once cancelled.
+        let _ = cancelled.wait_for(|cancelled| *cancelled).await;
+    }
+
+    /// Kills the process group of `pid` if the request is cancelled before the returned guard is
+    /// dropped. The process must have been spawned in its own process group.
+    pub fn track_process(&self, pid: Pid) -> TrackedProcess<'_> {
+        self.state.pids.lock().unwrap().push(pid);
+        if self.is_cancelled() {
+            kill_process_group(pid);
+        }
+        TrackedProcess { token: self, pid }
+    }
+
+    pub fn set_progress_token(&self, progress_token: ProgressToken) {
+        *self.state.progress_token.lock().unwrap() = Some(progress_token);
+    }
+
+    fn has_progress_token(&self, progress_token: &ProgressToken) -> bool {
+        self.state.progress_token.lock().unwrap().as_ref() == Some(progress_token)
+    }
+}
+
+pub struct TrackedProcess<'a> {
+    token: &'a CancellationToken,
+    pid: Pid,
+}
+
+impl Drop for TrackedProcess<'_> {
+    fn drop(&mut self) {
+        self.token
+            .state
+            .pids
+            .lock()
+            .unwrap()
+            .retain(|&pid| pid != self.pid);
+    }
+}
+
+/// The requests that have been read from the client but not yet answered, by id.
+#[derive(Clone, Default)]
+pub struct InFlightRequests {
+    requests: Arc<Mutex<HashMap<MessageId, CancellationToken>>>,
+}
+
+impl InFlightRequests {
+    /// Starts tracking a request as soon as it is read, so that it can be cancelled even before
+    /// the main loop gets to it.
+    pub fn register(&self, id: MessageId) {
+        self.requests.lock().unwrap().entry(id).or_default();
+    }
+
+    /// The cancellation token of a request.
+    pub fn token(&self, id: &MessageId) -> CancellationToken {
+        self.requests
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Stops tracking a request once it has been answered.
+    pub fn finish(&self, id: &MessageId) {
+        self.requests.lock().unwrap().remove(id);
+    }
+
+    /// Handles `$/cancelRequest`. Requests that have already been answered are ignored.
+    pub fn cancel(&self, id: &MessageId) {
+        if let Some(token) = self.requests.lock().unwrap().get(id) {
+            log::info!("cancelling request [id={id:?}]");
+            token.cancel();
+        }
+    }
+
+    /// Handles `window/workDoneProgress/cancel` by cancelling the request reporting the progress.
+    pub fn cancel_progress(&self, progress_token: &ProgressToken) {
+        for (id, token) in self.requests.lock().unwrap().iter() {
+            if token.has_progress_token(progress_token) {
+                log::info!("cancelling request [id={id:?}, progress_token={progress_token:?}]");
+                token.cancel();
+            }
+        }
+    }
+}
+
+#[test]
+fn test_cancel_request() {
+    use std::os::unix::process::CommandExt as _;
+
+    let in_flight = InFlightRequests::default();
+    let id = MessageId::Number(7);
+    in_flight.register(id.clone());
+    let token = in_flight.token(&id);
+    assert!(token.check().is_ok());
+
+    let mut child = Command::new("sleep")
+        .arg("30")
+        .process_group(0)
+        .spawn()
+        .unwrap();
+    let _tracked = token.track_process(Pid::from_raw(child.id() as i32));
+    in_flight.cancel(&id);
+    assert!(token.is_cancelled());
+    // The process group was killed rather than left to run to completion.
+    assert!(!child.wait().unwrap().success());
+    let error = token.check().unwrap_err();
+    assert!(error.is::<RequestCancelled>());
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(token.cancelled());
+
+    // Answered requests can no longer be cancelled.
+    in_flight.finish(&id);
+    in_flight.cancel(&id);
+    assert!(!in_flight.token(&id).is_cancelled());
+}
@@ -1,16 +1,22 @@
 #![allow(unused)]
-use std::io::Write;
+use std::{cell::Cell, io::Write};
+
+use lsp_types::request::Request;
 
 use crate::prelude::*;
 
 #[derive(Clone)]
 pub struct Client {
     stdout: Rc<RefCell<dyn Write>>,
+    next_request_id: Rc<Cell<i64>>,
 }
 
 impl Client {
     pub fn new(stdout: Rc<RefCell<dyn Write>>) -> Self {
-        Self { stdout }
+        Self {
+            stdout,
+            next_request_id: Rc::new(Cell::new(1)),
+        }
     }
     pub fn log_message(&self, message_type: MessageType, message: impl Into<String>) -> Result<()> {
         self.send_packet(
@@ -53,12 +59,27 @@ impl Client {
         )
         .unwrap()
     }
+    /// Sends a request to the client. The client's response is not waited for; it is logged when
+    /// it arrives.
+    pub fn send_request<R: Request>(&self, params: R::Params) -> Result<()> {
+        let id = self.next_request_id.get();
+        self.next_request_id.set(id + 1);
+        self.write_packet(&json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": R::METHOD,
+            "params": params,
+        }))
+    }
     fn send_packet(&self, method: &str, params: impl Serialize) -> Result<()> {
-        let json = serde_json::to_string(&json!({
+        self.write_packet(&json!({
             "jsonrpc": "2.0",
             "method": method,
             "params": params,
-        }))?;
+        }))
+    }
+    fn write_packet(&self, packet: &Value) -> Result<()> {
+        let json = serde_json::to_string(packet)?;
         let mut w = self.stdout.borrow_mut();
         log::trace!("Sending packet length: {}", json.len());
         write!(w, "Content-Length: {}\r\n\r\n{}", json.len(), json)?;
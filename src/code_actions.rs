@@ -0,0 +1,289 @@
+use crate::prelude::*;
+
+/// Whether a code action request asked for actions of `kind`. Kinds are hierarchical, so asking for
+/// "source" includes "source.fixAll".
+pub fn code_action_kind_requested(only: Option<&[CodeActionKind]>, kind: &CodeActionKind) -> bool {
+    let Some(only) = only else {
+        return true;
+    };
+    only.iter().any(|requested| {
+        kind.as_str()
+            .strip_prefix(requested.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    })
+}
+
+fn workspace_edit(uri: &Uri, edits: Vec<TextEdit>) -> WorkspaceEdit {
+    WorkspaceEdit {
+        changes: Some([(uri.clone(), edits)].into_iter().collect()),
+        document_changes: None,
+        change_annotations: None,
+    }
+}
+
+/// Offers the fixes attached to `diagnostics` (those the client sent along with a code action
+/// request) as quick fixes. Fixes are only offered if `linter_diagnostics` shows that their linter
+/// ran against `version` of the document, since edits computed for other versions would land in
+/// the wrong place.
+pub fn linter_quick_fixes(
+    uri: &Uri,
+    version: DocumentVersion,
+    diagnostics: &[Diagnostic],
+    linter_diagnostics: Option<&HashMap<LinterName, LinterDiagnostics>>,
+) -> Vec<CodeActionOrCommand> {
+    diagnostics
+        .iter()
+        .filter_map(|diagnostic| {
+            let data = PicklsDiagnosticData::from_diagnostic(diagnostic)?;
+            let fix = data.fix?;
+            if linter_diagnostics?.get(&data.linter)?.version != version {
+                log::info!(
+                    "not offering a stale fix [linter={linter}, uri={uri}]",
+                    linter = data.linter,
+                    uri = uri.as_str()
+                );
+                return None;
+            }
+            Some(CodeActionOrCommand::CodeAction(CodeAction {
+                title: fix
+                    .title
+                    .unwrap_or_else(|| format!("Apply fix from {linter}", linter = data.linter)),
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                edit: Some(workspace_edit(uri, fix.edits)),
+                command: None,
+                is_preferred: Some(true),
+                disabled: None,
+                data: None,
+            }))
+        })
+        .collect()
+}
+
+/// Offers a "fix all" action for each linter that has fixes for `version` of the document. Fixes
+/// that overlap an earlier fix are left out, so that the combined edit stays valid.
+pub fn linter_fix_all_actions(
+    uri: &Uri,
+    version: DocumentVersion,
+    linter_diagnostics: &HashMap<LinterName, LinterDiagnostics>,
+) -> Vec<CodeActionOrCommand> {
+    let mut linter_names: Vec<&LinterName> = linter_diagnostics.keys().collect();
+    linter_names.sort();
+    linter_names
+        .into_iter()
+        .filter_map(|linter_name| {
+            let entry = &linter_diagnostics[linter_name];
+            if entry.version != version {
+                return None;
+            }
+            let mut fixes: Vec<PicklsDiagnosticFix> = entry
+                .diagnostics
+                .iter()
+                .filter_map(|diagnostic| PicklsDiagnosticData::from_diagnostic(diagnostic)?.fix)
+                .filter(|fix| !fix.edits.is_empty())
+                .collect();
+            fixes.sort_by_key(|fix| fix.edits.iter().map(|edit| edit.range.start).min());
+            let mut edits: Vec<TextEdit> = Vec::new();
+            for fix in fixes {
+                let overlaps = fix.edits.iter().any(|edit| {
+                    edits.iter().any(|accepted| {
+                        (edit.range.start < accepted.range.end
+                            && accepted.range.start < edit.range.end)
+                            || edit.range.start == accepted.range.start
+                    })
+                });
+                if !overlaps {
+                    edits.extend(fix.edits);
+                }
+            }
+            if edits.is_empty() {
+                return None;
+            }
+            Some(CodeActionOrCommand::CodeAction(CodeAction {
+                title: format!("Fix all from {linter_name}"),
+                kind: Some(CodeActionKind::SOURCE_FIX_ALL),
+                diagnostics: None,
+                edit: Some(workspace_edit(uri, edits)),
+                command: None,
+                is_preferred: None,
+                disabled: None,
+                data: None,
+            }))
+        })
+        .collect()
+}
+
+/// The command that runs a fixer. Its arguments are the document's uri and the fixer's name.
+pub const FIX_COMMAND: &str = "pickls.fix";
+
+/// Offers a `source.fixAll.<name>` action for each of `fixers` that the request asked for. The
+/// actions carry a command rather than an edit, so that fixers only run when chosen.
+pub fn fixer_actions(
+    uri: &Uri,
+    only: Option<&[CodeActionKind]>,
+    fixers: &[PicklsFixerConfig],
+) -> Vec<CodeActionOrCommand> {
+    fixers
+        .iter()
+        .filter_map(|fixer| {
+            let kind = CodeActionKind::from(format!(
+                "{fix_all}.{name}",
+                fix_all = CodeActionKind::SOURCE_FIX_ALL.as_str(),
+                name = fixer.name()
+            ));
+            if !code_action_kind_requested(only, &kind) {
+                return None;
+            }
+            let title = format!("Fix all with {name}", name = fixer.name());
+            Some(CodeActionOrCommand::CodeAction(CodeAction {
+                title: title.clone(),
+                kind: Some(kind),
+                diagnostics: None,
+                edit: None,
+                command: Some(lsp_types::Command {
+                    title,
+                    command: FIX_COMMAND.to_string(),
+                    arguments: Some(vec![json!(uri.as_str()), json!(fixer.name())]),
+                }),
+                is_preferred: None,
+                disabled: None,
+                data: None,
+            }))
+        })
+        .collect()
+}
+
+/// Offers a quick fix that inserts a suppression comment for each of `diagnostics` whose linter has
+/// a `suppression_template` and that has a rule code. Positions are counted in `encoding`.
+pub fn suppression_actions(
+    uri: &Uri,
+    file_contents: &str,
+    diagnostics: &[Diagnostic],
+    linters: &[PicklsLinterConfig],
+    encoding: PositionEncoding,
+) -> Vec<CodeActionOrCommand> {
+    let lines: Vec<&str> = file_contents.split('\n').collect();
+    diagnostics
+        .iter()
+        .filter_map(|diagnostic| {
+            let data = PicklsDiagnosticData::from_diagnostic(diagnostic)?;
+            let linter = linters.iter().find(|linter| {
+                linter.program == data.linter && linter.suppression_template.is_some()
+            })?;
+            let code = match diagnostic.code.as_ref()? {
+                NumberOrString::String(code) => code.clone(),
+                NumberOrString::Number(code) => code.to_string(),
+            };
+            let comment = render_template(
+                linter.suppression_template.as_deref()?,
+                CodeTemplateContext {
+                    code: &code,
+                    linter: &linter.program,
+                },
+            )
+            .inspect_err(|error| {
+                log::warn!(
+                    "invalid suppression_template for {linter}: {error}",
+                    linter = linter.program
+                )
+            })
+            .ok()?;
+            let line = diagnostic.range.start.line;
+            let text = lines.get(line as usize)?;
+            let text = text.strip_suffix('\r').unwrap_or(text);
+            let edit = match linter.suppression_placement {
+                PicklsSuppressionPlacement::SameLine => {
+                    let end = Position::new(line, encoding.text_len(text));
+                    TextEdit {
+                        range: Range::new(end, end),
+                        new_text: format!(" {comment}"),
+                    }
+                }
+                PicklsSuppressionPlacement::PreviousLine => {
+                    let indentation: String =
+                        text.chars().take_while(|ch| ch.is_whitespace()).collect();
+                    let start = Position::new(line, 0);
+                    let line_ending = if text.len() < lines[line as usize].len() {
+                        "\r\n"
+                    } else {
+                        "\n"
+                    };
+                    TextEdit {
+                        range: Range::new(start, start),
+                        new_text: format!("{indentation}{comment}{line_ending}"),
+                    }
+                }
+            };
+            Some(CodeActionOrCommand::CodeAction(CodeAction {
+                title: format!("Suppress {code} from {linter}", linter = linter.program),
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                edit: Some(workspace_edit(uri, vec![edit])),
+                command: None,
+                is_preferred: None,
+                disabled: None,
+                data: None,
+            }))
+        })
+        .collect()
+}
+
+#[test]
+fn test_code_action_kind_requested() {
+    let only = [
+        CodeActionKind::SOURCE,
+        CodeActionKind::new("pickls.inline-assist"),
+    ];
+    assert!(code_action_kind_requested(None, &CodeActionKind::QUICKFIX));
+    assert!(code_action_kind_requested(
+        Some(&only),
+        &CodeActionKind::SOURCE_FIX_ALL
+    ));
+    assert!(code_action_kind_requested(
+        Some(&only),
+        &CodeActionKind::new("pickls.inline-assist")
+    ));
+    assert!(!code_action_kind_requested(
+        Some(&only),
+        &CodeActionKind::QUICKFIX
+    ));
+    assert!(!code_action_kind_requested(
+        Some(&[CodeActionKind::new("source.fix")]),
+        &CodeActionKind::SOURCE_FIX_ALL
+    ));
+}
+
+#[test]
+fn test_suppression_actions() {
+    let uri: Uri = "file:///src/a.sh".parse().unwrap();
+    let linters: Vec<PicklsLinterConfig> = serde_json::from_value(json!([{
+        "program": "shellcheck",
+        "use_stdin": true,
+        "suppression_template": "# shellcheck disable={{code}}",
+        "suppression_placement": "previous_line",
+    }]))
+    .unwrap();
+    let diagnostic = Diagnostic {
+        range: Range::new(Position::new(1, 7), Position::new(1, 9)),
+        code: Some(NumberOrString::String("SC2086".to_string())),
+        data: Some(json!({"linter": "shellcheck"})),
+        ..Default::default()
+    };
+    let actions = suppression_actions(
+        &uri,
+        "#!/bin/sh\n  echo $1\n",
+        &[diagnostic],
+        &linters,
+        PositionEncoding::Utf16,
+    );
+    let [CodeActionOrCommand::CodeAction(action)] = actions.as_slice() else {
+        panic!("expected a single code action, got {actions:?}");
+    };
+    assert_eq!(action.title, "Suppress SC2086 from shellcheck");
+    let edits = &action.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri];
+    assert_eq!(
+        edits[0].range,
+        Range::new(Position::new(1, 0), Position::new(1, 0))
+    );
+    assert_eq!(edits[0].new_text, "  # shellcheck disable=SC2086\n");
+}
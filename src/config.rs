@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use serde::Deserialize;
 
 const DEFAULT_CTAGS_TIMEOUT_MS: u64 = 500;
+const DEFAULT_FORMAT_ON_SAVE_TIMEOUT_MS: u64 = 1000;
 
 #[derive(Clone, Debug, Deserialize, Default)]
 pub struct PicklsConfig {
@@ -17,6 +18,10 @@ fn default_ctags_timeout_ms() -> u64 {
     DEFAULT_CTAGS_TIMEOUT_MS
 }
 
+fn default_format_on_save_timeout_ms() -> u64 {
+    DEFAULT_FORMAT_ON_SAVE_TIMEOUT_MS
+}
+
 #[derive(Eq, PartialEq, Clone, Debug, Deserialize)]
 pub struct PicklsSymbolsConfig {
     pub source: PicklsSymbolsSource,
@@ -42,15 +47,42 @@ pub struct PicklsLanguageConfig {
     /// All the formatters you'd like to run (in order) on this language. Note
     /// that you'll need to configure your editor to invoke its LSP client to
     /// cause formatting to occur. Successive formatters that set use_stdin will
-    /// have chained pipes from stdout to stdin to eliminate extra copies.
+    /// have chained pipes from stdout to stdin to eliminate extra copies. An entry may also be a
+    /// group of `alternatives`, of which only the first usable formatter runs.
+    #[serde(default)]
+    pub formatters: Vec<PicklsFormatterEntry>,
+
+    /// Tools that apply fixes to a document (ie: `ruff check --fix`). Fixers are configured like
+    /// formatters, but they only run when their `source.fixAll.<name>` code action (or the
+    /// `pickls.fix` command) is invoked.
     #[serde(default)]
-    pub formatters: Vec<PicklsFormatterConfig>,
+    pub fixers: Vec<PicklsFixerConfig>,
 
     /// Default root markers that will be inherited by linters and formatters if they
     /// don't specify their own. (ie: pyproject.toml, setup.py, Cargo.toml, go.mod,
     /// Makefile, etc...)
     #[serde(default)]
     pub root_markers: Vec<String>,
+
+    /// How long to wait (in milliseconds) after a document changes before running its linters.
+    /// Changes that arrive within this window restart the wait, so a burst of edits results in a
+    /// single lint of the latest text. Linters can override this with their own `debounce_ms`.
+    /// Defaults to 0 (no debouncing).
+    pub debounce_ms: Option<u64>,
+
+    /// Whether to format documents when they are saved (via `textDocument/willSaveWaitUntil`).
+    /// Auto-saves that happen after a delay are not formatted. Defaults to false.
+    #[serde(default = "default_false")]
+    pub format_on_save: bool,
+
+    /// How long (in milliseconds) formatting on save may take, in total, before the remaining
+    /// formatters are killed or skipped so that the save can proceed. Defaults to 1000.
+    #[serde(default = "default_format_on_save_timeout_ms")]
+    pub format_on_save_timeout_ms: u64,
+
+    /// Drop diagnostics less severe than this (ie: "warning" hides info and hint diagnostics).
+    /// Diagnostics without a severity are always kept. If not specified, nothing is dropped.
+    pub min_severity: Option<PicklsSeverity>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -72,35 +104,319 @@ pub struct PicklsLinterConfig {
     /// Whether to use stdin to push the contents of the file to `program` or to rely on the usage
     /// of "$filename" arg.
     pub use_stdin: bool,
+    /// Whether "$tmpfilename" should be created next to the real file (as a hidden file) rather
+    /// than in the system temp directory. This helps linters that resolve relative imports or
+    /// project configuration from the file's location. Defaults to false.
+    #[serde(default = "default_false")]
+    pub tmpfile_in_document_dir: bool,
+    /// How to parse the output of `program`. Defaults to `regex`.
+    /// - `regex`: match `pattern` against every line, pulling fields out with the `*_match` groups.
+    /// - `json`: walk the JSON output using the field paths in `json`.
+    /// - `sarif`: parse SARIF 2.1 output.
+    /// - `checkstyle`: parse checkstyle XML output.
+    #[serde(default)]
+    pub output_format: PicklsOutputFormat,
+    /// Field paths used to pull diagnostics out of JSON output. Required when `output_format` is
+    /// `json`.
+    pub json: Option<PicklsJsonOutputConfig>,
     /// Regex from which to pull diagnostics from stdout of `program`. The pattern is matched on
-    /// every line of output. When there is a match, a diagnostic is produced.
-    pub pattern: String,
+    /// every line of output. When there is a match, a diagnostic is produced. Required when
+    /// `output_format` is `regex`, unless `errorformat` is specified.
+    pub pattern: Option<String>,
+    /// A Vim `errorformat` describing the output of `program`, as an alternative to `pattern` and
+    /// the `*_match` groups. Supports the %f, %l, %c, %e, %k, %t, %m, %n, %p, %r and %s
+    /// specifiers, along with the %A, %E, %W, %I, %N, %C, %Z and %G markers (and their %- and %+
+    /// forms) for multi-line messages.
+    pub errorformat: Option<String>,
     /// Regex group (1-indexed) that matches the filename of the diagnostic.
     pub filename_match: Option<usize>,
-    /// Regex group (1-indexed) that matches the line number of the diagnostic.
-    pub line_match: usize,
+    /// Regex group (1-indexed) that matches the line number of the diagnostic. Required when
+    /// `output_format` is `regex`, unless `multiline.location` provides it.
+    pub line_match: Option<usize>,
     /// Regex group (1-indexed) that matches the starting column number of the diagnostic. (Optional)
     pub start_col_match: Option<usize>,
+    /// Regex group (1-indexed) that matches the ending line number of the diagnostic, for linters
+    /// that report ranges spanning multiple lines (ie: mypy --show-error-end). (Optional)
+    pub end_line_match: Option<usize>,
     /// Regex group (1-indexed) that matches the ending column number of the diagnostic. (Optional)
     pub end_col_match: Option<usize>,
-    /// Regex group (1-indexed) that matches the severity of the alert. Unknown severities will
-    /// resolve to warnings.
+    /// What the columns reported by the linter count, so that diagnostics land on the right
+    /// characters of lines with non-ASCII text:
+    /// - `utf16`: UTF-16 code units, as JavaScript tools and SARIF do. (default)
+    /// - `char`: Unicode characters (code points), as most Python and Rust tools do.
+    /// - `byte`: bytes of UTF-8, as gcc, clang and Go tools do.
+    #[serde(default)]
+    pub column_unit: PicklsColumnUnit,
+    /// The column number that the linter reports for the first character of a line (0 or 1).
+    /// Defaults to 1.
+    #[serde(default = "default_columns_start_at")]
+    pub columns_start_at: u32,
+    /// Regex group (1-indexed) that matches the severity of the alert. Severities are looked up in
+    /// `severity_map` first, then matched (case-insensitively) against the names error, warn,
+    /// warning, info, information, note and hint. Anything else resolves to an error.
     pub severity_match: Option<usize>,
+    /// Maps the severities reported by the linter to error, warning, info or hint. Keys are
+    /// matched case-insensitively, or as a regex when wrapped in slashes (ie: "/^C\d+$/"). Exact
+    /// keys take precedence over regexes.
+    #[serde(default)]
+    pub severity_map: HashMap<String, PicklsSeverity>,
+    /// The severity of diagnostics that don't report one (ie: when there is no
+    /// `severity_match`). If not specified, the client decides how to display them.
+    pub default_severity: Option<PicklsSeverity>,
+    /// Regex group (1-indexed) that matches the rule code of the diagnostic (ie: SC2086). (Optional)
+    pub code_match: Option<usize>,
+    /// Handlebars template for a link to the documentation of a diagnostic's rule code. The code is
+    /// available as `{{code}}` and the linter program as `{{linter}}` (ie:
+    /// "https://www.shellcheck.net/wiki/{{code}}"). Only used for diagnostics that have a code.
+    pub code_url_template: Option<String>,
+    /// Handlebars template of a comment that suppresses a diagnostic (ie: "# noqa: {{code}}" or
+    /// "// eslint-disable-next-line {{code}}"). If specified, diagnostics with a rule code get a
+    /// quick fix that inserts the comment. The code is available as `{{code}}` and the linter
+    /// program as `{{linter}}`.
+    pub suppression_template: Option<String>,
+    /// Where the suppression comment goes:
+    /// - `same_line`: at the end of the diagnostic's line. (default)
+    /// - `previous_line`: on a new line above the diagnostic's line, at the same indentation.
+    #[serde(default)]
+    pub suppression_placement: PicklsSuppressionPlacement,
+    /// Regex group (1-indexed) that matches a replacement for the text covered by the diagnostic.
+    /// When it matches, the replacement is offered as a quick fix. (Optional)
+    pub fix_match: Option<usize>,
     /// Regex group (1-indexed) that matches the line number of the diagnostic. Use -1 to indicate
     /// that the description is on the _previous_ line of input.
     pub description_match: Option<isize>,
+    /// Lets a single diagnostic span several lines of output, for tools that print a header line
+    /// followed by context lines (ie: gcc, rustc-style tools or elm). A line matching `pattern`
+    /// starts a diagnostic, and the lines that follow are folded into it until one of them ends
+    /// it or doesn't belong to it.
+    pub multiline: Option<PicklsMultilineConfig>,
     /// Whether to scan stderr instead of stdout. Defaults to false. Setting to true will ignore
     /// stdout.
     #[serde(default = "default_false")]
     pub use_stderr: bool,
+    /// Overrides the language-level `debounce_ms` for this linter. Useful for slow linters (ie:
+    /// mypy) that should wait for edits to settle while cheaper linters keep up with every change.
+    pub debounce_ms: Option<u64>,
+    /// When to run this linter. Every linter runs when a document is opened.
+    /// - `change`: also run whenever the document changes. (default)
+    /// - `save`: also run whenever the document is saved. Use this for linters that need to read
+    ///   the file from disk via "$filename". Their diagnostics persist across subsequent changes
+    ///   until the next save.
+    /// - `open`: only run when the document is opened.
+    #[serde(default)]
+    pub run_on: PicklsRunOn,
+    /// What the linter checks.
+    /// - `document`: the document being edited. (default)
+    /// - `workspace`: the whole project that the document belongs to (ie: mypy ., tsc --noEmit or
+    ///   cargo check). The linter runs in the root directory of the project when a document is
+    ///   saved (unless `run_on` is `open`), and when the first document of the project is opened.
+    ///   It never runs on changes.
+    ///   Its diagnostics are kept for every file that it reports on, and each run replaces those
+    ///   of the previous run.
+    #[serde(default)]
+    pub scope: PicklsLinterScope,
+    /// How long (in milliseconds) the linter may run before its process group is killed. A linter
+    /// that times out reports a single warning diagnostic saying so. If not specified, the linter
+    /// may run indefinitely.
+    pub timeout_ms: Option<u64>,
+    /// Conditions that must all hold for the linter to run on a document. If not specified, the
+    /// linter runs on every document of its language.
+    #[serde(default)]
+    pub when: PicklsConditions,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PicklsMultilineConfig {
+    /// Regex matching lines that continue the current diagnostic. If it has a capture group, the
+    /// first group is appended to the description. Otherwise the whole line is.
+    pub continuation: String,
+    /// Regex matching the line that ends the current diagnostic. If it has a capture group, the
+    /// first group is appended to the description. If not specified, a diagnostic ends at the
+    /// first line that doesn't continue it.
+    pub end: Option<String>,
+    /// Matches continuation lines that point at a location (ie: "  --> src/main.rs:3:5" or
+    /// "a.c:3:5: note: previous definition"). The first location of a diagnostic whose header has
+    /// no line number becomes its own location. Any others become related locations.
+    pub location: Option<PicklsLocationPattern>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PicklsLocationPattern {
+    pub pattern: String,
+    /// Regex group (1-indexed) that matches the filename of the location.
+    pub filename_match: Option<usize>,
+    /// Regex group (1-indexed) that matches the line number of the location.
+    pub line_match: usize,
+    /// Regex group (1-indexed) that matches the starting column number of the location.
+    pub start_col_match: Option<usize>,
+    /// Regex group (1-indexed) that matches a message describing the location.
+    pub description_match: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PicklsSeverity {
+    Error,
+    Warning,
+    #[serde(alias = "information")]
+    Info,
+    Hint,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PicklsOutputFormat {
+    #[default]
+    Regex,
+    Json,
+    Sarif,
+    Checkstyle,
+}
+
+/// Describes where to find diagnostics in a linter's JSON output. Paths are dot-separated object
+/// keys or array indices (ie: "location.row" or "locations.0.line"). Output that is not a single
+/// JSON document is read as one JSON document per line.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PicklsJsonOutputConfig {
+    /// Path to the array of diagnostics. A "[]" segment flattens an array along the way, so
+    /// eslint's `[{"filePath": ..., "messages": [...]}]` is described with "[].messages". Defaults
+    /// to the root of the output.
+    #[serde(default)]
+    pub diagnostics: String,
+    /// Path to the filename of a diagnostic. Prefix a path with "^." to look it up on the
+    /// enclosing object instead (ie: "^.filePath" for eslint). If not specified, diagnostics are
+    /// assumed to be for the current file.
+    pub filename: Option<String>,
+    /// Path to the (1-based) line number of a diagnostic.
+    pub line: String,
+    /// Path to the (1-based) starting column of a diagnostic.
+    pub column: Option<String>,
+    /// Path to the (1-based) ending line of a diagnostic.
+    pub end_line: Option<String>,
+    /// Path to the (1-based) ending column of a diagnostic.
+    pub end_column: Option<String>,
+    /// Whether `end_column` is the column of the last character of a diagnostic rather than the
+    /// column just past it. Defaults to false.
+    #[serde(default)]
+    pub end_column_inclusive: bool,
+    /// Path to the severity of a diagnostic.
+    pub severity: Option<String>,
+    /// Path to the rule code of a diagnostic (ie: "E501" or "SC2086").
+    pub code: Option<String>,
+    /// Path to the message of a diagnostic.
+    pub message: String,
+    /// Describes where to find the fix reported for a diagnostic, if any.
+    pub fix: Option<PicklsJsonFixConfig>,
+}
+
+/// Field paths of a fix in JSON output. For ruff:
+///
+/// ```yaml
+/// fix:
+///   edits: fix.edits
+///   text: content
+///   line: location.row
+///   column: location.column
+///   end_line: end_location.row
+///   end_column: end_location.column
+///   title: fix.message
+/// ```
+///
+/// And for eslint: `{text: fix.text, range: fix.range}`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PicklsJsonFixConfig {
+    /// Path (from the diagnostic) to the edit, or array of edits, that make up the fix. If not
+    /// specified, the edit fields are looked up on the diagnostic itself.
+    pub edits: Option<String>,
+    /// Path (from the edit) to the replacement text.
+    pub text: String,
+    /// Path (from the edit) to a `[start, end)` pair of offsets into the document, counted in the
+    /// linter's `column_unit`.
+    pub range: Option<String>,
+    /// Path (from the edit) to the (1-based) line where the edit starts. If neither this nor
+    /// `range` is found, the edit replaces the range of the diagnostic.
+    pub line: Option<String>,
+    /// Path (from the edit) to the (1-based) column where the edit starts.
+    pub column: Option<String>,
+    /// Path (from the edit) to the (1-based) line where the edit ends.
+    pub end_line: Option<String>,
+    /// Path (from the edit) to the (1-based, exclusive) column where the edit ends.
+    pub end_column: Option<String>,
+    /// Path (from the diagnostic) to a description of the fix.
+    pub title: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PicklsSuppressionPlacement {
+    #[default]
+    SameLine,
+    PreviousLine,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PicklsColumnUnit {
+    Byte,
+    Char,
+    #[default]
+    Utf16,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PicklsLinterScope {
+    #[default]
+    Document,
+    Workspace,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PicklsRunOn {
+    #[default]
+    Change,
+    Save,
+    Open,
+}
+
+impl PicklsRunOn {
+    /// Whether a linter configured with this `run_on` should run in response to `event`.
+    pub fn is_triggered_by(self, event: PicklsRunOn) -> bool {
+        event == PicklsRunOn::Open || self == event
+    }
+}
+
+/// Conditions under which a linter or formatter applies to a document, for workspaces that mix
+/// projects using different tools (ie: ruff in one project and flake8 in another).
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct PicklsConditions {
+    /// One of these files must exist in a directory above the document, up to the workspace
+    /// folder. (ie: ruff.toml, .flake8)
+    #[serde(default)]
+    pub root_markers: Vec<String>,
+    /// The document's path must match one of these globs (ie: "services/api/**/*.py"). Globs are
+    /// matched against the path relative to the workspace folder containing the document, as well
+    /// as against the absolute path. `*` does not match across `/`, while `**` does.
+    #[serde(default)]
+    pub globs: Vec<String>,
+    /// Whether `program` must be found (on `PATH`, unless it is a path). Defaults to false.
+    #[serde(default = "default_false")]
+    pub program_exists: bool,
+    /// A dot-separated key that must exist in the nearest pyproject.toml above the document (ie:
+    /// "tool.ruff").
+    pub pyproject_key: Option<String>,
+    /// A dot-separated key that must exist in the nearest package.json above the document (ie:
+    /// "devDependencies.eslint").
+    pub package_json_key: Option<String>,
 }
 
 fn default_false() -> bool {
     false
 }
 
-fn default_true() -> bool {
-    true
+fn default_columns_start_at() -> u32 {
+    1
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -115,16 +431,115 @@ pub struct PicklsFormatterConfig {
     /// If `program` is not an absolute path, the `PATH` will be searched in an OS-defined way.
     pub program: String,
     /// Arguments to pass to `program`. Use "$filename" wherever the absolute path to the filename should go.
+    /// Use "$tmpfilename" where Pickls should inject a temp file containing the current contents
+    /// of the document. Unless `output` says otherwise, the formatter is then expected to rewrite
+    /// that file in place, and its contents are used as the formatted result.
     #[serde(default = "Vec::new")]
     pub args: Vec<String>,
+    /// Arguments to use instead of `args` when formatting a range, for formatters that support
+    /// formatting part of a file (ie: clang-format `--lines=$start_line:$end_line` or black
+    /// `--line-ranges=$start_line-$end_line`). "$start_line" and "$end_line" are 1-based and
+    /// inclusive, while "$start_byte" and "$end_byte" are 0-based byte offsets with an exclusive
+    /// end. If not specified, range formatting formats the whole document and keeps only the
+    /// edits that touch the range.
+    pub range_args: Option<Vec<String>>,
     /// Whether to use stdin to push the contents of the file to `program` or to rely on the usage
-    /// of "$filename" arg. Defaults to true.
-    #[serde(default = "default_true")]
-    pub use_stdin: bool,
+    /// of "$filename" arg. Defaults to true, unless `output` is `in_place`, since such formatters
+    /// read the temp file instead.
+    pub use_stdin: Option<bool>,
+    /// Whether "$tmpfilename" should be created next to the real file (as a hidden file) rather
+    /// than in the system temp directory. Defaults to false.
+    #[serde(default = "default_false")]
+    pub tmpfile_in_document_dir: bool,
     /// If `stderr_indicates_error` is true, then if the formatter writes anything to stderr, the
     /// format run will be considered a failure and aborted. Defaults to false.
     #[serde(default = "default_false")]
     pub stderr_indicates_error: bool,
+    /// How long (in milliseconds) the formatter may run before its process group is killed and
+    /// the format run is considered a failure. If not specified, the formatter may run
+    /// indefinitely.
+    pub timeout_ms: Option<u64>,
+    /// How the formatter reports the formatted text:
+    /// - `stdout`: prints it. (default, unless `args` contain "$tmpfilename")
+    /// - `diff`: prints a unified diff against its input (ie: `gofmt -d`), which is applied to the
+    ///   document. Since many tools exit with a failure status when they print a diff, the exit
+    ///   status is ignored when there is a diff on stdout.
+    /// - `in_place`: rewrites the temp file "$tmpfilename". (default when `args` contain
+    ///   "$tmpfilename") If `args` don't mention "$tmpfilename", the temp file's path is appended
+    ///   to them.
+    pub output: Option<PicklsFormatterOutput>,
+    /// What to do when the formatter fails:
+    /// - `abort`: stop formatting and leave the document unchanged. (default)
+    /// - `skip`: ignore this formatter and pass its input on to the next one.
+    #[serde(default)]
+    pub on_error: PicklsFormatterOnError,
+    /// Conditions that must all hold for the formatter to run on a document. If not specified, the
+    /// formatter runs on every document of its language.
+    #[serde(default)]
+    pub when: PicklsConditions,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PicklsFormatterOutput {
+    Stdout,
+    Diff,
+    InPlace,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PicklsFormatterOnError {
+    Skip,
+    #[default]
+    Abort,
+}
+
+/// An entry in a language's list of formatters.
+#[derive(Clone, Debug)]
+pub enum PicklsFormatterEntry {
+    Formatter(PicklsFormatterConfig),
+    /// Formatters of which only the first usable one runs (ie: black if it is installed,
+    /// otherwise autopep8). A formatter is usable if its program is found (on `PATH`, unless it is
+    /// a path), its `when` conditions hold and, if it specifies its own `root_markers`, one of them
+    /// exists above the document.
+    Alternatives(Vec<PicklsFormatterConfig>),
+}
+
+impl<'de> Deserialize<'de> for PicklsFormatterEntry {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct AlternativesEntry {
+            alternatives: Vec<PicklsFormatterConfig>,
+        }
+        // Dispatch on the `alternatives` key rather than using an untagged enum, so that errors in
+        // a formatter's configuration are still reported precisely.
+        let value = serde_json::Value::deserialize(deserializer)?;
+        if value.get("alternatives").is_some() {
+            serde_json::from_value::<AlternativesEntry>(value)
+                .map(|entry| Self::Alternatives(entry.alternatives))
+                .map_err(serde::de::Error::custom)
+        } else {
+            serde_json::from_value(value)
+                .map(Self::Formatter)
+                .map_err(serde::de::Error::custom)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PicklsFixerConfig {
+    /// Names the fixer in its code action kind (`source.fixAll.<name>`) and in `pickls.fix`
+    /// commands. Defaults to `program`.
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub formatter: PicklsFormatterConfig,
+}
+
+impl PicklsFixerConfig {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.formatter.program)
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Default)]
@@ -6,38 +6,423 @@ pub struct PicklsDiagnostic {
     pub filename: String,
     pub line: u32,
     pub start_column: Option<u32>,
+    pub end_line: Option<u32>,
     pub end_column: Option<u32>,
     pub severity: Option<PicklsDiagnosticSeverity>,
+    pub code: Option<String>,
     pub description: Option<String>,
+    /// Other locations mentioned by the diagnostic (ie: notes pointing at a prior definition).
+    pub related: Vec<PicklsRelatedLocation>,
+    /// A fix for the diagnostic reported by the linter, offered as a quick fix.
+    pub fix: Option<PicklsFix>,
 }
 
-impl From<PicklsDiagnostic> for Diagnostic {
-    fn from(diag: PicklsDiagnostic) -> Self {
-        let line = diag.line.saturating_sub(1);
-        let start_column = diag.start_column.unwrap_or(1).saturating_sub(1);
-        let end_column = diag.end_column.unwrap_or(start_column + 1);
+#[derive(Debug)]
+pub struct PicklsRelatedLocation {
+    pub filename: String,
+    pub line: u32,
+    pub start_column: Option<u32>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct PicklsFix {
+    pub title: Option<String>,
+    pub edits: Vec<PicklsFixEdit>,
+}
+
+#[derive(Debug)]
+pub struct PicklsFixEdit {
+    pub range: PicklsFixRange,
+    pub new_text: String,
+}
+
+#[derive(Debug)]
+pub enum PicklsFixRange {
+    /// Replace the range of the diagnostic itself.
+    Diagnostic,
+    /// A range given by 1-based lines and by columns numbered from the linter's `columns_start_at`.
+    /// The end column is exclusive.
+    Positions {
+        line: u32,
+        column: Option<u32>,
+        end_line: Option<u32>,
+        end_column: Option<u32>,
+    },
+    /// A range given by offsets into the document (counted in the linter's `column_unit`), end
+    /// exclusive (ie: eslint).
+    Offsets { start: u32, end: u32 },
+}
+
+/// What pickls stores in `Diagnostic.data`. Clients hand it back along with the diagnostics of a
+/// code action request.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PicklsDiagnosticData {
+    pub linter: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fix: Option<PicklsDiagnosticFix>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PicklsDiagnosticFix {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub edits: Vec<TextEdit>,
+}
+
+impl PicklsDiagnosticData {
+    pub fn from_diagnostic(diagnostic: &Diagnostic) -> Option<Self> {
+        serde_json::from_value(diagnostic.data.clone()?).ok()
+    }
+}
+
+/// How the columns (and offsets) reported by a linter map onto the characters of LSP positions.
+#[derive(Clone, Copy, Debug)]
+pub struct ColumnMapping {
+    /// What the linter's columns count.
+    pub unit: PositionEncoding,
+    /// The column that the linter reports for the first character of a line.
+    pub start_at: u32,
+    /// What the characters of LSP positions count, as negotiated with the client.
+    pub encoding: PositionEncoding,
+}
+
+impl ColumnMapping {
+    pub fn new(linter_config: &PicklsLinterConfig, encoding: PositionEncoding) -> Self {
+        Self {
+            unit: linter_config.column_unit.into(),
+            start_at: linter_config.columns_start_at,
+            encoding,
+        }
+    }
+
+    /// Converts a 0-based `column` (counted in the linter's unit) of a 0-based `line` into the
+    /// character of an LSP position, clamped to the line.
+    pub fn character(&self, document_lines: &[&str], line: u32, column: u32) -> u32 {
+        self.unit
+            .convert_column(line_text(document_lines, line), column, self.encoding)
+    }
+}
+
+/// Returns the text of a line, without any carriage return.
+fn line_text<'a>(document_lines: &[&'a str], line: u32) -> &'a str {
+    document_lines
+        .get(line as usize)
+        .map_or("", |text| text.strip_suffix('\r').unwrap_or(text))
+}
+
+/// Clamps a 0-based position (counted in `encoding`) to the document.
+fn clamp_position(
+    document_lines: &[&str],
+    line: u32,
+    character: u32,
+    encoding: PositionEncoding,
+) -> Position {
+    let line = line.min((document_lines.len() as u32).saturating_sub(1));
+    Position {
+        line,
+        character: character.min(encoding.text_len(line_text(document_lines, line))),
+    }
+}
+
+/// Converts an offset into the document (counted in the linter's unit) into a position.
+fn offset_to_position(document_lines: &[&str], offset: u32, columns: ColumnMapping) -> Position {
+    let mut remaining = offset;
+    for (line, text) in document_lines.iter().enumerate() {
+        // Count the newline that split this line from the next.
+        let length = columns.unit.text_len(text) + 1;
+        if remaining < length {
+            return Position {
+                line: line as u32,
+                character: columns.character(document_lines, line as u32, remaining),
+            };
+        }
+        remaining -= length;
+    }
+    clamp_position(document_lines, u32::MAX, u32::MAX, columns.encoding)
+}
+
+impl PicklsFix {
+    /// Converts this into text edits, clamped to the document. `diagnostic_range` is the range of
+    /// the diagnostic that the fix belongs to.
+    pub fn into_diagnostic_fix(
+        self,
+        diagnostic_range: Range,
+        document_lines: &[&str],
+        columns: ColumnMapping,
+    ) -> PicklsDiagnosticFix {
+        let position = |line: u32, column: u32| {
+            let line = clamp_position(document_lines, line, 0, columns.encoding).line;
+            Position {
+                line,
+                character: columns.character(
+                    document_lines,
+                    line,
+                    column.saturating_sub(columns.start_at),
+                ),
+            }
+        };
+        let edits = self
+            .edits
+            .into_iter()
+            .map(|edit| {
+                let range = match edit.range {
+                    PicklsFixRange::Diagnostic => diagnostic_range,
+                    PicklsFixRange::Positions {
+                        line,
+                        column,
+                        end_line,
+                        end_column,
+                    } => {
+                        let start =
+                            position(line.saturating_sub(1), column.unwrap_or(columns.start_at));
+                        let end = match (end_line, end_column) {
+                            (None, None) => start,
+                            (end_line, end_column) => position(
+                                end_line.unwrap_or(line).saturating_sub(1),
+                                end_column.unwrap_or(u32::MAX),
+                            ),
+                        };
+                        Range {
+                            start,
+                            end: end.max(start),
+                        }
+                    }
+                    PicklsFixRange::Offsets { start, end } => Range {
+                        start: offset_to_position(document_lines, start, columns),
+                        end: offset_to_position(document_lines, end.max(start), columns),
+                    },
+                };
+                TextEdit {
+                    range,
+                    new_text: edit.new_text,
+                }
+            })
+            .collect();
+        PicklsDiagnosticFix {
+            title: self.title,
+            edits,
+        }
+    }
+}
+
+impl PicklsDiagnostic {
+    /// Converts this into an LSP diagnostic. `document_lines` are the lines of the text the linter
+    /// ran against; the range is clamped to them so that out-of-bounds lines and columns reported
+    /// by linters still land somewhere sensible. End columns are inclusive, like start columns.
+    pub fn into_diagnostic(self, document_lines: &[&str], columns: ColumnMapping) -> Diagnostic {
+        let character = |line: u32, column: u32| columns.character(document_lines, line, column);
+        let last_line = (document_lines.len() as u32).saturating_sub(1);
+
+        let line = self.line.saturating_sub(1).min(last_line);
+        let start_column = self
+            .start_column
+            .map_or(0, |column| column.saturating_sub(columns.start_at));
+        let start_character = character(line, start_column);
+        let end_line = self
+            .end_line
+            .map_or(line, |end_line| end_line.saturating_sub(1))
+            .clamp(line, last_line);
+        let end_character = match (self.end_column, self.end_line) {
+            // The column just past the inclusive end column.
+            (Some(end_column), _) => character(
+                end_line,
+                end_column
+                    .saturating_add(1)
+                    .saturating_sub(columns.start_at),
+            ),
+            // Without an end column, a multi-line range runs to the end of its last line.
+            (None, Some(_)) if end_line > line => character(end_line, u32::MAX),
+            // Otherwise, the range covers a single character.
+            (None, _) => character(line, start_column.saturating_add(1)),
+        };
+        let end_character = if end_line == line {
+            end_character.max(start_character)
+        } else {
+            end_character
+        };
 
         let range = Range {
             start: Position {
                 line,
-                character: start_column,
+                character: start_character,
             },
             end: Position {
-                line,
-                character: end_column,
+                line: end_line,
+                character: end_character,
             },
         };
 
-        Self {
+        let data = PicklsDiagnosticData {
+            linter: self.linter.clone(),
+            fix: self
+                .fix
+                .map(|fix| fix.into_diagnostic_fix(range, document_lines, columns)),
+        };
+        Diagnostic {
             range,
-            severity: diag.severity.map(DiagnosticSeverity::from),
-            code: None,
+            severity: self.severity.map(DiagnosticSeverity::from),
+            code: self.code.map(NumberOrString::String),
             code_description: None,
-            source: Some(format!("[pickls/{}]", diag.linter)),
-            message: diag.description.unwrap_or_else(|| "error".to_string()),
+            source: Some(format!("[pickls/{}]", self.linter)),
+            message: self.description.unwrap_or_else(|| "error".to_string()),
             related_information: None,
             tags: None,
-            data: None,
+            data: serde_json::to_value(data).ok(),
         }
     }
 }
+
+#[test]
+fn test_into_diagnostic_clamps_range() {
+    let document_lines: Vec<&str> = "fn main() {\r\n    let x = 1;\r\n}\n".split('\n').collect();
+    let columns = ColumnMapping {
+        unit: PositionEncoding::Utf16,
+        start_at: 1,
+        encoding: PositionEncoding::Utf16,
+    };
+    let diagnostic = |line, start_column, end_line, end_column| {
+        PicklsDiagnostic {
+            linter: "test".to_string(),
+            filename: "test.rs".to_string(),
+            line,
+            start_column,
+            end_line,
+            end_column,
+            severity: None,
+            code: None,
+            description: None,
+            related: Vec::new(),
+            fix: None,
+        }
+        .into_diagnostic(&document_lines, columns)
+        .range
+    };
+    let range = |start_line, start_character, end_line, end_character| Range {
+        start: Position::new(start_line, start_character),
+        end: Position::new(end_line, end_character),
+    };
+    // A single character by default.
+    assert_eq!(diagnostic(2, Some(5), None, None), range(1, 4, 1, 5));
+    // Columns past the end of the line are clamped, ignoring the carriage return.
+    assert_eq!(diagnostic(1, Some(40), None, Some(50)), range(0, 11, 0, 11));
+    // Lines past the end of the document land on the last line.
+    assert_eq!(diagnostic(9, None, None, None), range(3, 0, 3, 0));
+    // Multi-line ranges without an end column extend to the end of the last line.
+    assert_eq!(diagnostic(1, Some(1), Some(2), None), range(0, 0, 1, 14));
+    assert_eq!(diagnostic(1, Some(4), Some(3), Some(1)), range(0, 3, 2, 1));
+}
+
+#[test]
+fn test_into_diagnostic_maps_columns() {
+    // "é" is 2 bytes and 1 UTF-16 code unit, and "🌍" is 4 bytes and 2 UTF-16 code units.
+    let document_lines: Vec<&str> = "let s = \"é🌍\"; x\n".split('\n').collect();
+    let diagnostic = |start_column, end_column, unit, start_at, encoding| {
+        PicklsDiagnostic {
+            linter: "test".to_string(),
+            filename: "test.rs".to_string(),
+            line: 1,
+            start_column: Some(start_column),
+            end_line: None,
+            end_column,
+            severity: None,
+            code: None,
+            description: None,
+            related: Vec::new(),
+            fix: None,
+        }
+        .into_diagnostic(
+            &document_lines,
+            ColumnMapping {
+                unit,
+                start_at,
+                encoding,
+            },
+        )
+        .range
+    };
+    let columns = |start_character, end_character| {
+        Range::new(
+            Position::new(0, start_character),
+            Position::new(0, end_character),
+        )
+    };
+    // The `x`, reported as a 0-based byte offset.
+    assert_eq!(
+        diagnostic(18, None, PositionEncoding::Utf8, 0, PositionEncoding::Utf16),
+        columns(15, 16)
+    );
+    // The emoji, reported as a 1-based character column, lands on both of its UTF-16 code units
+    // or all four of its bytes.
+    assert_eq!(
+        diagnostic(
+            11,
+            None,
+            PositionEncoding::Utf32,
+            1,
+            PositionEncoding::Utf16
+        ),
+        columns(10, 12)
+    );
+    assert_eq!(
+        diagnostic(11, None, PositionEncoding::Utf32, 1, PositionEncoding::Utf8),
+        columns(11, 15)
+    );
+    // The string literal, reported in 1-based characters with an inclusive end.
+    assert_eq!(
+        diagnostic(
+            9,
+            Some(12),
+            PositionEncoding::Utf32,
+            1,
+            PositionEncoding::Utf16
+        ),
+        columns(8, 13)
+    );
+}
+
+#[test]
+fn test_fix_into_text_edits() {
+    let document_lines: Vec<&str> = "import os\nx = \"\u{e9}\" ;\n".split('\n').collect();
+    let diagnostic_range = Range::new(Position::new(0, 0), Position::new(0, 9));
+    let fix = PicklsFix {
+        title: Some("Remove import".to_string()),
+        edits: vec![
+            PicklsFixEdit {
+                range: PicklsFixRange::Diagnostic,
+                new_text: String::new(),
+            },
+            PicklsFixEdit {
+                range: PicklsFixRange::Positions {
+                    line: 2,
+                    column: Some(8),
+                    end_line: Some(2),
+                    end_column: Some(40),
+                },
+                new_text: ";".to_string(),
+            },
+            PicklsFixEdit {
+                range: PicklsFixRange::Offsets { start: 16, end: 18 },
+                new_text: String::new(),
+            },
+        ],
+    }
+    .into_diagnostic_fix(
+        diagnostic_range,
+        &document_lines,
+        ColumnMapping {
+            unit: PositionEncoding::Utf16,
+            start_at: 1,
+            encoding: PositionEncoding::Utf16,
+        },
+    );
+    let ranges: Vec<Range> = fix.edits.iter().map(|edit| edit.range).collect();
+    assert_eq!(
+        ranges,
+        vec![
+            diagnostic_range,
+            Range::new(Position::new(1, 7), Position::new(1, 9)),
+            Range::new(Position::new(1, 6), Position::new(1, 8)),
+        ]
+    );
+}
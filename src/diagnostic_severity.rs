@@ -18,3 +18,106 @@ impl From<PicklsDiagnosticSeverity> for DiagnosticSeverity {
         }
     }
 }
+
+impl From<PicklsSeverity> for DiagnosticSeverity {
+    fn from(severity: PicklsSeverity) -> Self {
+        match severity {
+            PicklsSeverity::Error => DiagnosticSeverity::ERROR,
+            PicklsSeverity::Warning => DiagnosticSeverity::WARNING,
+            PicklsSeverity::Info => DiagnosticSeverity::INFORMATION,
+            PicklsSeverity::Hint => DiagnosticSeverity::HINT,
+        }
+    }
+}
+
+/// Resolves the severities reported by a linter according to its `severity_map` and
+/// `default_severity`.
+pub struct SeverityResolver {
+    exact: HashMap<String, DiagnosticSeverity>,
+    patterns: Vec<(Regex, DiagnosticSeverity)>,
+    default_severity: Option<DiagnosticSeverity>,
+}
+
+impl SeverityResolver {
+    pub fn new(linter_config: &PicklsLinterConfig) -> Result<Self> {
+        let mut exact = HashMap::new();
+        let mut patterns = Vec::new();
+        // Sort so that overlapping regexes are tried in a predictable order.
+        let mut entries = linter_config.severity_map.iter().collect::<Vec<_>>();
+        entries.sort_by_key(|(key, _)| key.as_str());
+        for (key, &severity) in entries {
+            match key
+                .strip_prefix('/')
+                .and_then(|key| key.strip_suffix('/'))
+                .filter(|pattern| !pattern.is_empty())
+            {
+                Some(pattern) => {
+                    let re = Regex::new(pattern).with_context(|| {
+                        format!(
+                            "invalid severity_map regex in linter configuration of `{program}` [pattern={pattern}]",
+                            program = linter_config.program
+                        )
+                    })?;
+                    patterns.push((re, severity.into()));
+                }
+                None => {
+                    exact.insert(key.to_lowercase(), severity.into());
+                }
+            }
+        }
+        Ok(Self {
+            exact,
+            patterns,
+            default_severity: linter_config.default_severity.map(DiagnosticSeverity::from),
+        })
+    }
+
+    pub fn resolve(
+        &self,
+        severity: Option<&PicklsDiagnosticSeverity>,
+    ) -> Option<DiagnosticSeverity> {
+        let Some(PicklsDiagnosticSeverity { severity }) = severity else {
+            return self.default_severity;
+        };
+        if let Some(&mapped) = self.exact.get(&severity.to_lowercase()) {
+            return Some(mapped);
+        }
+        if let Some((_, mapped)) = self.patterns.iter().find(|(re, _)| re.is_match(severity)) {
+            return Some(*mapped);
+        }
+        Some(
+            PicklsDiagnosticSeverity {
+                severity: severity.clone(),
+            }
+            .into(),
+        )
+    }
+}
+
+#[test]
+fn test_severity_resolver() {
+    let linter_config: PicklsLinterConfig = serde_json::from_value(json!({
+        "program": "pylint",
+        "use_stdin": true,
+        "severity_map": {
+            "Convention": "hint",
+            "/^R\\d+$/": "info",
+            "2": "error",
+        },
+        "default_severity": "warning",
+    }))
+    .unwrap();
+    let resolver = SeverityResolver::new(&linter_config).unwrap();
+    let resolve = |severity: &str| {
+        resolver.resolve(Some(&PicklsDiagnosticSeverity {
+            severity: severity.to_string(),
+        }))
+    };
+    assert_eq!(resolve("convention"), Some(DiagnosticSeverity::HINT));
+    assert_eq!(resolve("R0913"), Some(DiagnosticSeverity::INFORMATION));
+    assert_eq!(resolve("2"), Some(DiagnosticSeverity::ERROR));
+    // Unmapped severities fall back to the built-in names.
+    assert_eq!(resolve("note"), Some(DiagnosticSeverity::INFORMATION));
+    assert_eq!(resolve("fatal"), Some(DiagnosticSeverity::ERROR));
+    assert_eq!(resolver.resolve(None), Some(DiagnosticSeverity::WARNING));
+}
@@ -1,3 +1,5 @@
+use std::collections::hash_map::Entry;
+
 use crate::prelude::*;
 
 pub(crate) type LinterName = String;
@@ -5,6 +7,13 @@ pub(crate) type LinterName = String;
 pub(crate) struct DiagnosticsManager {
     client: Client,
     diagnostics_storage: HashMap<Uri, DocumentDiagnostics>,
+    /// Whether the client pulls diagnostics (via `textDocument/diagnostic`) rather than having
+    /// them pushed (via `textDocument/publishDiagnostics`).
+    pull: bool,
+    next_result_id: u64,
+    /// The files that each workspace-scoped linter last reported on, so that the files it no
+    /// longer reports on can be cleared when it runs again.
+    workspace_reports: HashMap<WorkspaceJobId, HashSet<Uri>>,
 }
 
 impl DiagnosticsManager {
@@ -12,15 +21,110 @@ impl DiagnosticsManager {
         Self {
             client,
             diagnostics_storage: Default::default(),
+            pull: false,
+            next_result_id: 1,
+            workspace_reports: Default::default(),
         }
     }
 
+    pub(crate) fn set_pull(&mut self, pull: bool) {
+        self.pull = pull;
+    }
+
+    /// Reports the current diagnostics for a particular uri to a client that pulls them. If the
+    /// client already has them (per `previous_result_id`), they are reported as unchanged.
+    pub(crate) fn document_diagnostic_report(
+        &self,
+        uri: &Uri,
+        previous_result_id: Option<String>,
+    ) -> DocumentDiagnosticReportResult {
+        let document_diagnostics = self.diagnostics_storage.get(uri);
+        let result_id = document_diagnostics
+            .and_then(|document_diagnostics| document_diagnostics.result_id.clone());
+        let report = match result_id {
+            Some(result_id) if previous_result_id.as_ref() == Some(&result_id) => {
+                DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
+                    related_documents: None,
+                    unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport {
+                        result_id,
+                    },
+                })
+            }
+            result_id => DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+                related_documents: None,
+                full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                    result_id,
+                    items: document_diagnostics
+                        .map(DocumentDiagnostics::current_diagnostics)
+                        .unwrap_or_default(),
+                },
+            }),
+        };
+        DocumentDiagnosticReportResult::Report(report)
+    }
+
+    /// Reports the current diagnostics of every file that we have diagnostics for to a client that
+    /// pulls them. Files that the client already has the diagnostics of (per
+    /// `previous_result_ids`) are reported as unchanged.
+    pub(crate) fn workspace_diagnostic_report(
+        &self,
+        previous_result_ids: Vec<PreviousResultId>,
+    ) -> WorkspaceDiagnosticReportResult {
+        let previous_result_ids: HashMap<Uri, String> = previous_result_ids
+            .into_iter()
+            .map(|previous_result_id| (previous_result_id.uri, previous_result_id.value))
+            .collect();
+        let items = self
+            .diagnostics_storage
+            .iter()
+            .filter_map(|(uri, document_diagnostics)| {
+                let result_id = document_diagnostics.result_id.clone()?;
+                Some(if previous_result_ids.get(uri) == Some(&result_id) {
+                    WorkspaceDocumentDiagnosticReport::Unchanged(
+                        WorkspaceUnchangedDocumentDiagnosticReport {
+                            uri: uri.clone(),
+                            version: None,
+                            unchanged_document_diagnostic_report:
+                                UnchangedDocumentDiagnosticReport { result_id },
+                        },
+                    )
+                } else {
+                    WorkspaceDocumentDiagnosticReport::Full(WorkspaceFullDocumentDiagnosticReport {
+                        uri: uri.clone(),
+                        version: None,
+                        full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                            result_id: Some(result_id),
+                            items: document_diagnostics.current_diagnostics(),
+                        },
+                    })
+                })
+            })
+            .collect();
+        WorkspaceDiagnosticReportResult::Report(WorkspaceDiagnosticReport { items })
+    }
+
+    /// Whether a workspace-scoped linter has reported on its project yet.
+    pub(crate) fn has_workspace_report(&self, job_id: &WorkspaceJobId) -> bool {
+        self.workspace_reports.contains_key(job_id)
+    }
+
+    /// The most recent diagnostics of each linter for a particular uri.
+    pub(crate) fn linter_diagnostics(
+        &self,
+        uri: &Uri,
+    ) -> Option<&HashMap<LinterName, LinterDiagnostics>> {
+        self.diagnostics_storage
+            .get(uri)
+            .map(|document_diagnostics| &document_diagnostics.linter_diagnostics)
+    }
+
     /// Push new diagnostics for a particular uri, linter, and version. This is called
     /// after a linter has finished running.
     pub(crate) fn update_diagnostics(
         &mut self,
         uri: Uri,
         linter_name: String,
+        run_on: PicklsRunOn,
         max_linter_count: usize,
         version: DocumentVersion,
         new_diagnostics: Vec<Diagnostic>,
@@ -34,9 +138,13 @@ impl DiagnosticsManager {
 
         let document_diagnostics: &mut DocumentDiagnostics =
             self.diagnostics_storage.get_mut(&uri).unwrap();
+        // The number of linters that apply to a document can change as the files that their
+        // conditions depend on come and go.
+        document_diagnostics.max_linter_count = max_linter_count;
         if document_diagnostics.update_diagnostics_storage(
             &uri,
             &linter_name,
+            run_on,
             version,
             new_diagnostics,
         ) {
@@ -45,23 +153,404 @@ impl DiagnosticsManager {
             // other linters.
             let (uri, version, diagnostics, progress_messages) =
                 document_diagnostics.aggregate_most_recent_diagnostics(uri);
+            document_diagnostics.result_id = Some(self.next_result_id.to_string());
+            self.next_result_id += 1;
+            if self.pull {
+                if !document_diagnostics.is_complete() {
+                    // Wait for the remaining linters, so that the client pulls once per version
+                    // rather than once per linter.
+                    log::trace!(
+                        "not refreshing diagnostics until all linters have reported [linter={linter_name}, uri={uri}, version={version}]",
+                        uri = uri.as_str()
+                    );
+                    return self.send_progress(progress_messages);
+                }
+                // Ask the client to pull the new diagnostics.
+                log::info!(
+                    "refreshing diagnostics [linter={linter_name}, uri={uri}, version={version}, count={count}]",
+                    uri = uri.as_str(),
+                    count = diagnostics.len()
+                );
+                self.client
+                    .send_request::<lsp_types::request::WorkspaceDiagnosticRefresh>(())?;
+            } else {
+                log::info!(
+                    "publishing diagnostics [linter={linter_name}, uri={uri}, version={version}, count={count}]",
+                    uri = uri.as_str(),
+                    count = diagnostics.len()
+                );
+                self.client
+                    .publish_diagnostics(uri.clone(), diagnostics, Some(version.0));
+            }
+            return self.send_progress(progress_messages);
+        }
+        Ok(())
+    }
+
+    fn send_progress(&self, progress_messages: Vec<ProgressParams>) -> Result<()> {
+        for progress_message in progress_messages.into_iter() {
+            self.client
+                .send_notification::<Progress, _>(progress_message)?;
+        }
+        Ok(())
+    }
+
+    /// Forgets the diagnostics of a document's own linters once it is closed, and clears them from
+    /// the client. Those that workspace-scoped linters reported for it are kept, as they are for
+    /// files that aren't open.
+    pub(crate) fn close_document(&mut self, uri: &Uri) -> Result<()> {
+        let Some(document_diagnostics) = self.diagnostics_storage.get_mut(uri) else {
+            return Ok(());
+        };
+        let mut progress_messages = Vec::new();
+        if !document_diagnostics.is_complete() {
+            // The linters that were still running won't report, so their progress has to end here.
+            let version = *document_diagnostics.versions.last().unwrap();
+            progress_messages.push(ProgressParams {
+                token: progress_token(uri, version),
+                value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(WorkDoneProgressEnd {
+                    message: None,
+                })),
+            });
+        }
+        document_diagnostics
+            .linter_diagnostics
+            .retain(|_, entry| entry.scope == PicklsLinterScope::Workspace);
+        document_diagnostics.max_linter_count = 0;
+        // The versions of a document start over when it is reopened, so what remains is kept as of
+        // no particular version, like the diagnostics of files that aren't open.
+        document_diagnostics.versions = BTreeSet::from([DocumentVersion::default()]);
+        document_diagnostics.result_id = Some(self.next_result_id.to_string());
+        self.next_result_id += 1;
+        let diagnostics = document_diagnostics.current_diagnostics();
+        if !self.pull {
             log::info!(
-                "publishing diagnostics [linter={linter_name}, uri={uri}, version={version}, count={count}]",
+                "publishing diagnostics of closed document [uri={uri}, count={count}]",
                 uri = uri.as_str(),
                 count = diagnostics.len()
             );
             self.client
-                .publish_diagnostics(uri.clone(), diagnostics, Some(version.0));
-
-            for progress_message in progress_messages.into_iter() {
-                if let e @ Err(_) = self
-                    .client
-                    .send_notification::<Progress, _>(progress_message)
-                {
-                    return e;
-                }
+                .publish_diagnostics(uri.clone(), diagnostics.clone(), None);
+        }
+        if diagnostics.is_empty() {
+            self.diagnostics_storage.remove(uri);
+        }
+        self.send_progress(progress_messages)
+    }
+
+    /// Replace the diagnostics of a workspace-scoped linter with those of its latest run over a
+    /// project. Files that the previous run reported on but this one doesn't are cleared.
+    pub(crate) fn update_workspace_diagnostics(
+        &mut self,
+        job_id: WorkspaceJobId,
+        mut new_diagnostics: HashMap<Uri, Vec<Diagnostic>>,
+    ) -> Result<()> {
+        let reported_uris: HashSet<Uri> = new_diagnostics.keys().cloned().collect();
+        let previous_uris = self
+            .workspace_reports
+            .insert(job_id.clone(), reported_uris)
+            .unwrap_or_default();
+        for uri in previous_uris {
+            new_diagnostics.entry(uri).or_default();
+        }
+        let mut updated = false;
+        for (uri, diagnostics) in new_diagnostics {
+            let Some(document_diagnostics) = (match self.diagnostics_storage.entry(uri.clone()) {
+                // There is nothing to clear for files that we have no diagnostics for.
+                Entry::Vacant(_) if diagnostics.is_empty() => None,
+                // Files that aren't open still get diagnostics, as of no particular version.
+                Entry::Vacant(entry) => Some(entry.insert(DocumentDiagnostics::new(
+                    uri.clone(),
+                    0,
+                    DocumentVersion::default(),
+                ))),
+                Entry::Occupied(entry) => Some(entry.into_mut()),
+            }) else {
+                continue;
+            };
+            document_diagnostics
+                .update_workspace_diagnostics_storage(&job_id.linter_name, diagnostics);
+            document_diagnostics.result_id = Some(self.next_result_id.to_string());
+            self.next_result_id += 1;
+            updated = true;
+            if !self.pull {
+                let diagnostics = document_diagnostics.current_diagnostics();
+                log::info!(
+                    "publishing workspace diagnostics [linter={linter_name}, uri={uri}, count={count}]",
+                    linter_name = job_id.linter_name,
+                    uri = uri.as_str(),
+                    count = diagnostics.len()
+                );
+                self.client.publish_diagnostics(uri, diagnostics, None);
             }
         }
+        if self.pull && updated {
+            // Ask the client to pull the new diagnostics.
+            log::info!("refreshing diagnostics [job_id={job_id}]");
+            self.client
+                .send_request::<lsp_types::request::WorkspaceDiagnosticRefresh>(())?;
+        }
         Ok(())
     }
 }
+
+#[test]
+fn test_document_diagnostic_report() {
+    let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut diagnostics_manager = DiagnosticsManager::new(Client::new(output.clone()));
+    diagnostics_manager.set_pull(true);
+    let uri: Uri = "file:///src/a.py".parse().unwrap();
+    let report = |diagnostics_manager: &DiagnosticsManager, previous_result_id: Option<&str>| {
+        match diagnostics_manager
+            .document_diagnostic_report(&uri, previous_result_id.map(str::to_string))
+        {
+            DocumentDiagnosticReportResult::Report(report) => report,
+            DocumentDiagnosticReportResult::Partial(_) => panic!("unexpected partial report"),
+        }
+    };
+    let DocumentDiagnosticReport::Full(full) = report(&diagnostics_manager, None) else {
+        panic!("expected a full report");
+    };
+    assert!(full.full_document_diagnostic_report.items.is_empty());
+
+    let diagnostic = Diagnostic {
+        message: "undefined name".to_string(),
+        ..Default::default()
+    };
+    diagnostics_manager
+        .update_diagnostics(
+            uri.clone(),
+            "ruff".to_string(),
+            PicklsRunOn::Change,
+            1,
+            DocumentVersion(1),
+            vec![diagnostic.clone()],
+        )
+        .unwrap();
+    // Pulling clients are asked to pull again rather than sent the diagnostics.
+    let output = String::from_utf8(output.borrow().clone()).unwrap();
+    assert!(output.contains("workspace/diagnostic/refresh"), "{output}");
+    assert!(
+        !output.contains("textDocument/publishDiagnostics"),
+        "{output}"
+    );
+
+    let DocumentDiagnosticReport::Full(full) = report(&diagnostics_manager, Some("0")) else {
+        panic!("expected a full report");
+    };
+    let result_id = full.full_document_diagnostic_report.result_id.unwrap();
+    assert_eq!(full.full_document_diagnostic_report.items, vec![diagnostic]);
+    let DocumentDiagnosticReport::Unchanged(unchanged) =
+        report(&diagnostics_manager, Some(&result_id))
+    else {
+        panic!("expected an unchanged report");
+    };
+    assert_eq!(
+        unchanged.unchanged_document_diagnostic_report.result_id,
+        result_id
+    );
+}
+
+#[test]
+fn test_refresh_once_per_version() {
+    let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut diagnostics_manager = DiagnosticsManager::new(Client::new(output.clone()));
+    diagnostics_manager.set_pull(true);
+    let uri: Uri = "file:///src/a.py".parse().unwrap();
+    let refreshes = || {
+        String::from_utf8(output.borrow().clone())
+            .unwrap()
+            .matches("workspace/diagnostic/refresh")
+            .count()
+    };
+    for version in 1..=2 {
+        for linter_name in ["ruff", "mypy"] {
+            diagnostics_manager
+                .update_diagnostics(
+                    uri.clone(),
+                    linter_name.to_string(),
+                    PicklsRunOn::Change,
+                    2,
+                    DocumentVersion(version),
+                    Vec::new(),
+                )
+                .unwrap();
+        }
+        assert_eq!(refreshes(), version as usize);
+    }
+}
+
+#[test]
+fn test_update_workspace_diagnostics() {
+    let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut diagnostics_manager = DiagnosticsManager::new(Client::new(output.clone()));
+    let job_id = WorkspaceJobId {
+        linter_name: "mypy".to_string(),
+        root_dir: "/src".to_string(),
+    };
+    let a: Uri = "file:///src/a.py".parse().unwrap();
+    let b: Uri = "file:///src/b.py".parse().unwrap();
+    let diagnostic = |message: &str| Diagnostic {
+        message: message.to_string(),
+        ..Default::default()
+    };
+    // The document-scoped diagnostics of an open document are kept alongside.
+    diagnostics_manager
+        .update_diagnostics(
+            a.clone(),
+            "ruff".to_string(),
+            PicklsRunOn::Change,
+            1,
+            DocumentVersion(3),
+            vec![diagnostic("unused import")],
+        )
+        .unwrap();
+    assert!(!diagnostics_manager.has_workspace_report(&job_id));
+    diagnostics_manager
+        .update_workspace_diagnostics(
+            job_id.clone(),
+            HashMap::from([
+                (a.clone(), vec![diagnostic("incompatible types")]),
+                (b.clone(), vec![diagnostic("missing return")]),
+            ]),
+        )
+        .unwrap();
+    assert!(diagnostics_manager.has_workspace_report(&job_id));
+    let messages = |diagnostics_manager: &DiagnosticsManager, uri: &Uri| {
+        let mut messages: Vec<String> = diagnostics_manager.diagnostics_storage[uri]
+            .current_diagnostics()
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+        messages.sort();
+        messages
+    };
+    assert_eq!(
+        messages(&diagnostics_manager, &a),
+        vec!["incompatible types", "unused import"]
+    );
+    assert_eq!(messages(&diagnostics_manager, &b), vec!["missing return"]);
+
+    // Files that the next run doesn't report on are cleared.
+    diagnostics_manager
+        .update_workspace_diagnostics(
+            job_id,
+            HashMap::from([(a.clone(), vec![diagnostic("incompatible types")])]),
+        )
+        .unwrap();
+    assert_eq!(
+        messages(&diagnostics_manager, &a),
+        vec!["incompatible types", "unused import"]
+    );
+    assert!(messages(&diagnostics_manager, &b).is_empty());
+
+    let WorkspaceDiagnosticReportResult::Report(report) =
+        diagnostics_manager.workspace_diagnostic_report(Vec::new())
+    else {
+        panic!("unexpected partial report");
+    };
+    let mut uris: Vec<&str> = report
+        .items
+        .iter()
+        .map(|item| match item {
+            WorkspaceDocumentDiagnosticReport::Full(full) => full.uri.as_str(),
+            WorkspaceDocumentDiagnosticReport::Unchanged(_) => panic!("expected full reports"),
+        })
+        .collect();
+    uris.sort();
+    assert_eq!(uris, vec![a.as_str(), b.as_str()]);
+}
+
+#[test]
+fn test_close_document() {
+    let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut diagnostics_manager = DiagnosticsManager::new(Client::new(output.clone()));
+    let a: Uri = "file:///src/a.py".parse().unwrap();
+    let b: Uri = "file:///src/b.py".parse().unwrap();
+    let diagnostic = |message: &str| Diagnostic {
+        message: message.to_string(),
+        ..Default::default()
+    };
+    for uri in [&a, &b] {
+        diagnostics_manager
+            .update_diagnostics(
+                uri.clone(),
+                "ruff".to_string(),
+                PicklsRunOn::Change,
+                1,
+                DocumentVersion(1),
+                vec![diagnostic("unused import")],
+            )
+            .unwrap();
+    }
+    let job_id = WorkspaceJobId {
+        linter_name: "mypy".to_string(),
+        root_dir: "/src".to_string(),
+    };
+    diagnostics_manager
+        .update_workspace_diagnostics(
+            job_id,
+            HashMap::from([(b.clone(), vec![diagnostic("missing return")])]),
+        )
+        .unwrap();
+    diagnostics_manager
+        .update_diagnostics(
+            b.clone(),
+            "ruff".to_string(),
+            PicklsRunOn::Change,
+            1,
+            DocumentVersion(3),
+            vec![diagnostic("unused import")],
+        )
+        .unwrap();
+
+    // The diagnostics of a closed document are cleared from the client.
+    output.borrow_mut().clear();
+    diagnostics_manager.close_document(&a).unwrap();
+    let published = String::from_utf8(output.borrow().clone()).unwrap();
+    assert!(
+        published.contains("textDocument/publishDiagnostics")
+            && published.contains(r#""diagnostics":[]"#),
+        "{published}"
+    );
+    assert!(diagnostics_manager.linter_diagnostics(&a).is_none());
+
+    // Unless a workspace-scoped linter reported on it.
+    diagnostics_manager.close_document(&b).unwrap();
+    let linter_diagnostics = diagnostics_manager.linter_diagnostics(&b).unwrap();
+    assert_eq!(linter_diagnostics.keys().collect::<Vec<_>>(), vec!["mypy"]);
+
+    // A reopened document starts over at its first version.
+    diagnostics_manager
+        .update_diagnostics(
+            b.clone(),
+            "ruff".to_string(),
+            PicklsRunOn::Change,
+            1,
+            DocumentVersion(1),
+            vec![diagnostic("unused import")],
+        )
+        .unwrap();
+    let mut linters: Vec<_> = diagnostics_manager
+        .linter_diagnostics(&b)
+        .unwrap()
+        .keys()
+        .collect();
+    linters.sort();
+    assert_eq!(linters, vec!["mypy", "ruff"]);
+
+    // Clients that pull diagnostics drop those of closed documents themselves.
+    diagnostics_manager.set_pull(true);
+    diagnostics_manager
+        .update_diagnostics(
+            a.clone(),
+            "ruff".to_string(),
+            PicklsRunOn::Change,
+            1,
+            DocumentVersion(1),
+            vec![diagnostic("unused import")],
+        )
+        .unwrap();
+    diagnostics_manager.close_document(&a).unwrap();
+    assert!(diagnostics_manager.linter_diagnostics(&a).is_none());
+}
@@ -4,8 +4,22 @@ pub(crate) struct DocumentDiagnostics {
     #[allow(dead_code)]
     pub(crate) uri: Uri,
     pub(crate) max_linter_count: usize,
-    pub(crate) linter_diagnostics: HashMap<LinterName, Vec<Diagnostic>>,
+    pub(crate) linter_diagnostics: HashMap<LinterName, LinterDiagnostics>,
     pub(crate) versions: BTreeSet<DocumentVersion>,
+    /// Identifies the current set of diagnostics in pull-model reports, so that clients that
+    /// already have it can be told that nothing changed.
+    pub(crate) result_id: Option<String>,
+}
+
+pub(crate) struct LinterDiagnostics {
+    /// Linters that do not run on every change keep their diagnostics across new versions of the
+    /// document until they run again.
+    pub(crate) run_on: PicklsRunOn,
+    /// Workspace-scoped linters don't count towards the progress of linting a document.
+    pub(crate) scope: PicklsLinterScope,
+    /// The version of the document that the linter ran against.
+    pub(crate) version: DocumentVersion,
+    pub(crate) diagnostics: Vec<Diagnostic>,
 }
 
 impl DocumentDiagnostics {
@@ -15,6 +29,7 @@ impl DocumentDiagnostics {
             max_linter_count,
             linter_diagnostics: Default::default(),
             versions: BTreeSet::from([version]),
+            result_id: None,
         }
     }
 }
@@ -26,10 +41,24 @@ impl DocumentDiagnostics {
         &mut self,
         uri: &Uri,
         linter_name: &str,
+        run_on: PicklsRunOn,
         version: DocumentVersion,
-        mut new_diagnostics: Vec<Diagnostic>,
+        new_diagnostics: Vec<Diagnostic>,
     ) -> bool {
         let max_version = self.versions.last().cloned().unwrap_or(version);
+        let new_entry = LinterDiagnostics {
+            run_on,
+            scope: PicklsLinterScope::Document,
+            version,
+            diagnostics: new_diagnostics,
+        };
+        if max_version > version && run_on != PicklsRunOn::Change {
+            // Linters that run on save (or open) are not re-run as the document changes, so their
+            // results remain the best information available even if the document has moved on.
+            self.linter_diagnostics
+                .insert(linter_name.to_string(), new_entry);
+            return true;
+        }
         if max_version > version {
             log::info!(
                 "ignoring diagnostics for version {version} of {uri} from linter {linter_name} because it is older than the most recent version {max_version}",
@@ -38,26 +67,49 @@ impl DocumentDiagnostics {
             return false;
         }
         if version > max_version {
-            // We've got a new version of the file. Forget all prior diagnostics.
-            self.linter_diagnostics.clear();
+            // We've got a new version of the file. Forget all prior diagnostics from linters that
+            // will be re-run for it.
+            self.linter_diagnostics
+                .retain(|_, entry| entry.run_on != PicklsRunOn::Change);
         }
 
         self.linter_diagnostics
-            .entry(linter_name.to_string())
-            .and_modify(|e| std::mem::swap(e, &mut new_diagnostics))
-            .or_insert_with(|| new_diagnostics);
+            .insert(linter_name.to_string(), new_entry);
 
         // Remember all versions that have started progress messages.
         self.versions.insert(version);
         true
     }
 
+    /// Replaces the diagnostics that a workspace-scoped linter reported for this document. They
+    /// persist across new versions of the document until the linter runs again.
+    pub(crate) fn update_workspace_diagnostics_storage(
+        &mut self,
+        linter_name: &str,
+        new_diagnostics: Vec<Diagnostic>,
+    ) {
+        if new_diagnostics.is_empty() {
+            self.linter_diagnostics.remove(linter_name);
+            return;
+        }
+        let version = *self.versions.last().unwrap();
+        self.linter_diagnostics.insert(
+            linter_name.to_string(),
+            LinterDiagnostics {
+                run_on: PicklsRunOn::Save,
+                scope: PicklsLinterScope::Workspace,
+                version,
+                diagnostics: new_diagnostics,
+            },
+        );
+    }
+
     pub(crate) fn aggregate_most_recent_diagnostics(
         &mut self,
         uri: Uri,
     ) -> (Uri, DocumentVersion, Vec<Diagnostic>, Vec<ProgressParams>) {
         let max_version = *self.versions.last().unwrap();
-        let available = self.linter_diagnostics.len();
+        let available = self.available_linter_count();
         let mut progress_messages = vec![
             // Always create at least one progress message to denote the current update.
             make_progress_params(
@@ -81,14 +133,32 @@ impl DocumentDiagnostics {
         (
             uri,
             *self.versions.last().unwrap(),
-            self.linter_diagnostics
-                .values()
-                .flatten()
-                .cloned()
-                .collect::<Vec<_>>(),
+            self.current_diagnostics(),
             progress_messages,
         )
     }
+
+    /// The number of the document's own linters that have reported on it.
+    pub(crate) fn available_linter_count(&self) -> usize {
+        self.linter_diagnostics
+            .values()
+            .filter(|entry| entry.scope == PicklsLinterScope::Document)
+            .count()
+    }
+
+    /// Whether all of the document's own linters have reported on it.
+    pub(crate) fn is_complete(&self) -> bool {
+        self.available_linter_count() >= self.max_linter_count
+    }
+
+    /// The diagnostics of all linters, as they stand.
+    pub(crate) fn current_diagnostics(&self) -> Vec<Diagnostic> {
+        self.linter_diagnostics
+            .values()
+            .flat_map(|entry| entry.diagnostics.iter())
+            .cloned()
+            .collect()
+    }
 }
 
 pub fn make_progress_params<T: Into<String>>(
@@ -123,6 +193,6 @@ pub fn make_progress_params<T: Into<String>>(
     }
 }
 
-fn progress_token(uri: &Uri, version: DocumentVersion) -> ProgressToken {
+pub fn progress_token(uri: &Uri, version: DocumentVersion) -> ProgressToken {
     ProgressToken::String(format!("{}:{}", uri.as_str(), version))
 }
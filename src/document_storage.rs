@@ -1,4 +1,4 @@
-use crate::document_version::DocumentVersion;
+use crate::prelude::*;
 
 #[derive(Clone, Debug)]
 pub(crate) struct DocumentStorage {
@@ -6,3 +6,72 @@ pub(crate) struct DocumentStorage {
     pub(crate) file_contents: String,
     pub(crate) version: DocumentVersion,
 }
+
+impl DocumentStorage {
+    /// Applies the changes of a `textDocument/didChange` notification, in order. Changes with a
+    /// range replace that range (with positions counted in `encoding`), and changes without one
+    /// replace the whole document, as clients that only support full sync send them.
+    pub(crate) fn apply_content_changes(
+        &mut self,
+        changes: Vec<TextDocumentContentChangeEvent>,
+        encoding: PositionEncoding,
+    ) {
+        for change in changes {
+            let Some(range) = change.range else {
+                self.file_contents = change.text;
+                continue;
+            };
+            let start = position_to_byte_offset(&self.file_contents, range.start, encoding);
+            let end = position_to_byte_offset(&self.file_contents, range.end, encoding).max(start);
+            self.file_contents.replace_range(start..end, &change.text);
+        }
+    }
+}
+
+#[test]
+fn test_apply_content_changes() {
+    let change =
+        |range: Option<((u32, u32), (u32, u32))>, text: &str| TextDocumentContentChangeEvent {
+            range: range.map(
+                |((start_line, start_character), (end_line, end_character))| Range {
+                    start: Position::new(start_line, start_character),
+                    end: Position::new(end_line, end_character),
+                },
+            ),
+            range_length: None,
+            text: text.to_string(),
+        };
+    let mut document = DocumentStorage {
+        language_id: "text".to_string(),
+        file_contents: "fn main() {\n    println!(\"héllo 🌍\");\n}\n".to_string(),
+        version: DocumentVersion(1),
+    };
+    document.apply_content_changes(
+        vec![
+            // Replace the emoji, which spans two UTF-16 code units.
+            change(Some(((1, 20), (1, 22))), "world"),
+            // Insert a line, then delete it again along with the newline before it.
+            change(Some(((2, 1), (2, 1))), "\n// done"),
+            change(Some(((2, 1), (3, 7))), ""),
+            // Positions past the end of a line clamp to the end of the line.
+            change(Some(((0, 11), (0, 99))), " "),
+        ],
+        PositionEncoding::Utf16,
+    );
+    assert_eq!(
+        document.file_contents,
+        "fn main() { \n    println!(\"héllo world\");\n}\n"
+    );
+    document.apply_content_changes(
+        vec![change(None, "a\nb\n"), change(Some(((1, 0), (2, 0))), "")],
+        PositionEncoding::Utf16,
+    );
+    assert_eq!(document.file_contents, "a\n");
+    // Clients that negotiated UTF-8 positions count bytes.
+    document.file_contents = "é🌍x\n".to_string();
+    document.apply_content_changes(
+        vec![change(Some(((0, 2), (0, 6))), "")],
+        PositionEncoding::Utf8,
+    );
+    assert_eq!(document.file_contents, "éx\n");
+}
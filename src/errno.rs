@@ -6,7 +6,7 @@ use libc::strerror;
 #[cfg(target_os = "linux")]
 pub mod errors {
     use libc::__errno_location;
-    pub fn get_errno() -> c_int {
+    pub fn get_errno() -> libc::c_int {
         unsafe { *__errno_location() }
     }
 }
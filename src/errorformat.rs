@@ -0,0 +1,466 @@
+use std::io::{BufRead, BufReader, Read};
+
+use regex::Captures;
+
+use crate::prelude::*;
+
+/// A compiled Vim `errorformat` (see `:help errorformat`). Each comma-separated entry is
+/// translated into a regex plus the list of fields captured by its groups.
+pub struct Errorformat {
+    entries: Vec<ErrorformatEntry>,
+}
+
+struct ErrorformatEntry {
+    kind: EntryKind,
+    disposition: Disposition,
+    regex: Regex,
+    fields: Vec<Field>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EntryKind {
+    /// A complete diagnostic on a single line.
+    Single,
+    /// `%A`, `%E`, `%W`, `%I` or `%N`: the start of a multi-line diagnostic, along with the type
+    /// implied by the marker.
+    Start(Option<char>),
+    /// `%C`: a continuation of the current multi-line diagnostic.
+    Continue,
+    /// `%Z`: the end of the current multi-line diagnostic.
+    End,
+    /// `%G`: a general message that isn't tied to a location.
+    General,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Disposition {
+    Normal,
+    /// `%-`: the matching line is consumed but not used.
+    Ignore,
+    /// `%+`: the whole matching line is used as the message.
+    WholeLine,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    File,
+    Line,
+    Column,
+    EndLine,
+    EndColumn,
+    Type,
+    Message,
+    Number,
+    Pointer,
+}
+
+impl Errorformat {
+    pub fn new(errorformat: &str) -> Result<Self> {
+        let mut entries = Vec::new();
+        for entry in split_entries(errorformat) {
+            if let Some(entry) = ErrorformatEntry::new(&entry)
+                .with_context(|| format!("invalid errorformat entry [entry={entry}]"))?
+            {
+                entries.push(entry);
+            }
+        }
+        if entries.is_empty() {
+            anyhow::bail!("errorformat has no usable entries [errorformat={errorformat}]");
+        }
+        Ok(Self { entries })
+    }
+
+    /// Parses linter output into diagnostics. Diagnostics that don't name a file are attributed to
+    /// `default_filename`, and diagnostics without a line number are dropped.
+    pub fn parse(
+        &self,
+        linter: &str,
+        lines: impl IntoIterator<Item = String>,
+        default_filename: &str,
+    ) -> Vec<PicklsDiagnostic> {
+        let mut diagnostics = Vec::new();
+        // The multi-line diagnostic that is still accumulating lines, if any.
+        let mut open: Option<ErrorformatFields> = None;
+        let mut finish = |fields: ErrorformatFields| {
+            if let Some(diagnostic) = fields.into_diagnostic(linter, default_filename) {
+                diagnostics.push(diagnostic);
+            }
+        };
+        for line in lines {
+            let Some((entry, caps)) = self
+                .entries
+                .iter()
+                .find_map(|entry| Some((entry, entry.regex.captures(&line)?)))
+            else {
+                log::trace!("line did not match errorformat: {line}");
+                continue;
+            };
+            let ignore = entry.disposition == Disposition::Ignore;
+            match entry.kind {
+                EntryKind::General => {}
+                EntryKind::Single => {
+                    open.take().map(&mut finish);
+                    if !ignore {
+                        finish(entry.fields(&caps, &line, None));
+                    }
+                }
+                EntryKind::Start(kind) => {
+                    open.take().map(&mut finish);
+                    if !ignore {
+                        open = Some(entry.fields(&caps, &line, kind));
+                    }
+                }
+                EntryKind::Continue | EntryKind::End => {
+                    if let Some(fields) = open.as_mut()
+                        && !ignore
+                    {
+                        fields.merge(entry.fields(&caps, &line, None));
+                    }
+                    if entry.kind == EntryKind::End {
+                        open.take().map(&mut finish);
+                    }
+                }
+            }
+        }
+        open.map(finish);
+        diagnostics
+    }
+}
+
+/// Reads linter output and parses it with the linter's `errorformat`.
+pub fn parse_errorformat_output(
+    uri: &Uri,
+    linter_config: &PicklsLinterConfig,
+    errorformat: &str,
+    child_stdout: BufReader<impl Read>,
+) -> Result<Vec<PicklsDiagnostic>> {
+    if linter_config.pattern.is_some() {
+        anyhow::bail!(
+            "linter `{program}` specifies both pattern and errorformat",
+            program = linter_config.program
+        );
+    }
+    let errorformat = Errorformat::new(errorformat)?;
+    let lines = child_stdout
+        .lines()
+        .map(|line| {
+            Ok(String::from_utf8_lossy(&strip_ansi_escapes::strip(line?.as_bytes())).to_string())
+        })
+        .collect::<Result<Vec<String>>>()?;
+    Ok(errorformat.parse(&linter_config.program, lines, &uri_to_path(uri)))
+}
+
+/// Splits an errorformat on commas, honoring `\,` as a literal comma.
+fn split_entries(errorformat: &str) -> Vec<String> {
+    let mut entries = vec![String::new()];
+    let mut chars = errorformat.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' if chars.peek() == Some(&',') => {
+                entries.last_mut().unwrap().push(',');
+                chars.next();
+            }
+            ',' => entries.push(String::new()),
+            ch => entries.last_mut().unwrap().push(ch),
+        }
+    }
+    entries.retain(|entry| !entry.is_empty());
+    entries
+}
+
+impl ErrorformatEntry {
+    /// Compiles a single entry. Returns `None` for entries using markers that have no equivalent
+    /// here (ie: the `%P`/`%Q` file stack), which are skipped with a warning.
+    fn new(entry: &str) -> Result<Option<Self>> {
+        let mut rest = entry;
+        let mut disposition = Disposition::Normal;
+        let mut kind = EntryKind::Single;
+        if let Some(marker) = rest.strip_prefix('%') {
+            let mut marker_chars = marker.chars();
+            let mut marker_char = marker_chars.next();
+            let prefix = match marker_char {
+                Some('-') => Some(Disposition::Ignore),
+                Some('+') => Some(Disposition::WholeLine),
+                _ => None,
+            };
+            if let Some(prefix) = prefix {
+                disposition = prefix;
+                marker_char = marker_chars.next();
+            }
+            let marker_kind = match marker_char {
+                Some('A') => Some(EntryKind::Start(None)),
+                Some('E') => Some(EntryKind::Start(Some('e'))),
+                Some('W') => Some(EntryKind::Start(Some('w'))),
+                Some('I') => Some(EntryKind::Start(Some('i'))),
+                Some('N') => Some(EntryKind::Start(Some('n'))),
+                Some('C') => Some(EntryKind::Continue),
+                Some('Z') => Some(EntryKind::End),
+                Some('G') => Some(EntryKind::General),
+                Some('O' | 'P' | 'Q' | '>') => {
+                    log::warn!("ignoring unsupported errorformat entry [entry={entry}]");
+                    return Ok(None);
+                }
+                _ if prefix.is_some() => anyhow::bail!("%- and %+ must precede a marker"),
+                _ => None,
+            };
+            if let Some(marker_kind) = marker_kind {
+                kind = marker_kind;
+                rest = marker_chars.as_str();
+            }
+        }
+        let (pattern, fields) = translate_pattern(rest)?;
+        let regex = Regex::new(&format!("^(?:{pattern})$"))
+            .with_context(|| format!("invalid regex [pattern={pattern}]"))?;
+        Ok(Some(Self {
+            kind,
+            disposition,
+            regex,
+            fields,
+        }))
+    }
+
+    fn fields(&self, caps: &Captures, line: &str, kind: Option<char>) -> ErrorformatFields {
+        let mut fields = ErrorformatFields {
+            kind,
+            ..Default::default()
+        };
+        for (field, value) in self.fields.iter().zip(caps.iter().skip(1)) {
+            let Some(value) = value.map(|m| m.as_str()) else {
+                continue;
+            };
+            match field {
+                Field::File => fields.filename = Some(value.to_string()),
+                Field::Line => fields.line = value.parse().ok(),
+                Field::Column => fields.start_column = value.parse().ok(),
+                Field::EndLine => fields.end_line = value.parse().ok(),
+                Field::EndColumn => fields.end_column = value.parse().ok(),
+                Field::Type => fields.kind = value.chars().next().or(fields.kind),
+                Field::Message => fields.message = Some(value.to_string()),
+                Field::Number => fields.code = Some(value.to_string()),
+                // The pointer (ie: "    ^") sits under the column it points at.
+                Field::Pointer => fields.start_column = Some(value.chars().count() as u32 + 1),
+            }
+        }
+        if self.disposition == Disposition::WholeLine {
+            fields.message = Some(line.to_string());
+        }
+        fields
+    }
+}
+
+/// Translates the body of an errorformat entry (Vim regex plus `%` specifiers) into a regex and
+/// the fields captured by its groups, in order.
+fn translate_pattern(pattern: &str) -> Result<(String, Vec<Field>)> {
+    let mut regex = String::new();
+    let mut fields = Vec::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '%' => {
+                let specifier = chars.next().context("trailing % in errorformat")?;
+                let (group, field) = match specifier {
+                    'f' => ("(.+?)", Some(Field::File)),
+                    'l' => (r"(\d+)", Some(Field::Line)),
+                    'c' => (r"(\d+)", Some(Field::Column)),
+                    // Virtual columns count a tab as however many columns reach the linter's next
+                    // tab stop, and the linter's tab width is unknown.
+                    'v' => anyhow::bail!("unsupported errorformat specifier %v, use %c instead"),
+                    'e' => (r"(\d+)", Some(Field::EndLine)),
+                    'k' => (r"(\d+)", Some(Field::EndColumn)),
+                    'n' => (r"(\d+)", Some(Field::Number)),
+                    't' => ("(.)", Some(Field::Type)),
+                    'm' => ("(.*)", Some(Field::Message)),
+                    'p' => (r"([-. \t]*)", Some(Field::Pointer)),
+                    'r' | 's' => ("(?:.*)", None),
+                    '*' => {
+                        // `%*` repeats the following atom (ie: `%*\d` or `%*[^:]`).
+                        let ch = chars.next().context("trailing %* in errorformat")?;
+                        regex.push_str(&translate_vim_atom(ch, &mut chars)?);
+                        regex.push('*');
+                        continue;
+                    }
+                    '%' => ("%", None),
+                    // These spell out the Vim regex characters that they name, so `%.%#` is `.*`.
+                    '.' => (".", None),
+                    '#' => ("*", None),
+                    '^' => ("^", None),
+                    '$' => ("$", None),
+                    '~' => ("~", None),
+                    '[' | '\\' => {
+                        regex.push_str(&translate_vim_atom(specifier, &mut chars)?);
+                        continue;
+                    }
+                    specifier => anyhow::bail!("unsupported errorformat specifier %{specifier}"),
+                };
+                regex.push_str(group);
+                fields.extend(field);
+            }
+            ch => regex.push_str(&translate_vim_atom(ch, &mut chars)?),
+        }
+    }
+    Ok((regex, fields))
+}
+
+/// Translates a single atom of a (magic) Vim regex, starting with `ch`.
+fn translate_vim_atom(ch: char, chars: &mut impl Iterator<Item = char>) -> Result<String> {
+    Ok(match ch {
+        '.' | '*' | '^' | '$' => ch.to_string(),
+        '\\' => match chars.next() {
+            Some(class @ ('s' | 'S' | 'd' | 'D' | 'w' | 'W')) => format!("\\{class}"),
+            // Vim groups don't capture fields, so they must not shift the field groups either.
+            Some('(') => "(?:".to_string(),
+            Some(')') => ")".to_string(),
+            Some('|') => "|".to_string(),
+            Some(escaped) => regex::escape(&escaped.to_string()),
+            None => r"\\".to_string(),
+        },
+        '[' => {
+            let mut class = String::from("[");
+            let mut first = true;
+            loop {
+                let ch = chars.next().context("unterminated [] in errorformat")?;
+                match ch {
+                    ']' if !first => break,
+                    '^' if first => {
+                        class.push('^');
+                        continue;
+                    }
+                    '[' | ']' => class.push_str(&regex::escape(&ch.to_string())),
+                    '\\' => {
+                        class.push('\\');
+                        class.push(chars.next().context("unterminated [] in errorformat")?);
+                    }
+                    ch => class.push(ch),
+                }
+                first = false;
+            }
+            class.push(']');
+            class
+        }
+        ch => regex::escape(&ch.to_string()),
+    })
+}
+
+/// The fields gathered for one diagnostic, possibly over several lines.
+#[derive(Default)]
+struct ErrorformatFields {
+    filename: Option<String>,
+    line: Option<u32>,
+    start_column: Option<u32>,
+    end_line: Option<u32>,
+    end_column: Option<u32>,
+    kind: Option<char>,
+    code: Option<String>,
+    message: Option<String>,
+}
+
+impl ErrorformatFields {
+    /// Folds the fields of a continuation line into these. Locations are only filled in if they
+    /// are still missing, while messages accumulate.
+    fn merge(&mut self, other: ErrorformatFields) {
+        self.filename = self.filename.take().or(other.filename);
+        self.line = self.line.or(other.line);
+        self.start_column = self.start_column.or(other.start_column);
+        self.end_line = self.end_line.or(other.end_line);
+        self.end_column = self.end_column.or(other.end_column);
+        self.kind = self.kind.or(other.kind);
+        self.code = self.code.take().or(other.code);
+        match (&mut self.message, other.message) {
+            (Some(message), Some(other)) if !other.trim().is_empty() => {
+                message.push('\n');
+                message.push_str(&other);
+            }
+            (message @ None, other) => *message = other,
+            _ => {}
+        }
+    }
+
+    fn into_diagnostic(self, linter: &str, default_filename: &str) -> Option<PicklsDiagnostic> {
+        let Some(line) = self.line.filter(|&line| line > 0) else {
+            log::trace!("dropping errorformat diagnostic without a line number");
+            return None;
+        };
+        let severity = self.kind.map(|kind| match kind.to_ascii_lowercase() {
+            'e' => "error".to_string(),
+            'w' => "warning".to_string(),
+            'i' => "info".to_string(),
+            'n' => "note".to_string(),
+            kind => kind.to_string(),
+        });
+        Some(PicklsDiagnostic {
+            linter: linter.to_string(),
+            filename: self
+                .filename
+                .unwrap_or_else(|| default_filename.to_string()),
+            line,
+            start_column: self.start_column,
+            end_line: self.end_line,
+            end_column: self.end_column,
+            severity: severity.map(|severity| PicklsDiagnosticSeverity { severity }),
+            code: self.code,
+            description: self.message,
+            related: Vec::new(),
+            fix: None,
+        })
+    }
+}
+
+#[test]
+fn test_errorformat() {
+    let errorformat = Errorformat::new(concat!(
+        r"%f:%l:%c: %trror: %m \[E%n],",
+        r"%f:%l: %tarning: %m,",
+        r"%E%f:%l:%c: error: %m,",
+        r"%-C%*\d | %.%#,",
+        r"%C%p^,",
+        r"%Z%\s%#note: %m,",
+        r"%-G%.%#",
+    ))
+    .unwrap();
+    let output = [
+        "a.c:3:5: error: expected ';'",
+        "3 | int x",
+        "      ^",
+        "  note: add it",
+        "b.c:1:2: error: bad, really [E42]",
+        "x.c:9: warning: unused",
+        "make: *** [all] Error 1",
+    ];
+    let diagnostics = errorformat.parse("cc", output.map(String::from), "/src/doc.c");
+    assert_eq!(diagnostics.len(), 3);
+    assert_eq!(diagnostics[0].filename, "a.c");
+    assert_eq!(diagnostics[0].line, 3);
+    // The pointer line doesn't override the column of the header.
+    assert_eq!(diagnostics[0].start_column, Some(5));
+    assert_eq!(
+        diagnostics[0].description.as_deref(),
+        Some("expected ';'\nadd it")
+    );
+    assert_eq!(diagnostics[0].severity.as_ref().unwrap().severity, "error");
+    assert_eq!(diagnostics[1].code.as_deref(), Some("42"));
+    assert_eq!(diagnostics[1].description.as_deref(), Some("bad, really"));
+    assert_eq!(
+        diagnostics[2].severity.as_ref().unwrap().severity,
+        "warning"
+    );
+    assert_eq!(diagnostics[2].start_column, None);
+
+    // Virtual columns depend on the linter's tab width.
+    assert!(Errorformat::new("%f:%l:%v: %m").is_err());
+
+    // Lines without a filename are about the document.
+    let linter_config: PicklsLinterConfig = serde_json::from_value(json!({
+        "program": "lint",
+        "use_stdin": false,
+    }))
+    .unwrap();
+    let uri: Uri = "file:///src/my%20doc.c".parse().unwrap();
+    let diagnostics = parse_errorformat_output(
+        &uri,
+        &linter_config,
+        "%l: %m",
+        BufReader::new("4: oops\n".as_bytes()),
+    )
+    .unwrap();
+    assert_eq!(diagnostics[0].filename, "/src/my doc.c");
+}
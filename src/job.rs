@@ -1,3 +1,11 @@
+use std::{
+    process::{Child, ExitStatus},
+    sync::{Arc, Mutex},
+};
+
+use crossbeam_channel::RecvTimeoutError;
+use nix::sys::wait::{Id, WaitPidFlag, waitid};
+
 use crate::prelude::*;
 
 #[derive(Debug, Clone, Hash, Ord, PartialOrd, Eq, PartialEq)]
@@ -15,6 +23,23 @@ impl From<&JobSpec> for JobId {
     }
 }
 
+/// Identifies the run of a workspace-scoped linter over a particular project. Only one such run is
+/// kept going at a time.
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct WorkspaceJobId {
+    pub linter_name: String,
+    pub root_dir: String,
+}
+
+impl std::fmt::Display for WorkspaceJobId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WorkspaceJobId")
+            .field("linter_name", &self.linter_name)
+            .field("root_dir", &self.root_dir)
+            .finish()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct JobSpec {
     pub uri: Uri,
@@ -24,20 +49,186 @@ pub struct JobSpec {
 }
 
 pub struct Job {
-    pub pid: Pid,
+    pub process_group: ProcessGroup,
+    pub run_on: PicklsRunOn,
 }
 
 impl Job {
     pub fn spawn_kill(self) {
-        // NOTE: Because we called process_group on the subprocess, its pid == its pgid.
-        log::info!("killing job [pgid={pid}]", pid = self.pid);
-        let errno =
-            Errno::from(unsafe { nix::libc::killpg(self.pid.as_raw(), nix::libc::SIGKILL) });
-        if errno.is_error() {
-            log::trace!(
-                "failed to kill job [pid={pid}, error={errno}]",
-                pid = self.pid
-            );
+        self.process_group.kill();
+    }
+}
+
+/// The process group of a job that runs in the background, which can be killed from the main loop
+/// for as long as its process hasn't been reaped (see `reap`).
+#[derive(Clone, Debug)]
+pub struct ProcessGroup {
+    pid: Pid,
+    reaped: Arc<Mutex<bool>>,
+}
+
+impl ProcessGroup {
+    pub fn new(pid: Pid) -> Self {
+        Self {
+            pid,
+            reaped: Default::default(),
         }
     }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Kills the process group, unless its process has been reaped already.
+    pub fn kill(&self) {
+        // Holding the lock while killing keeps the process from being reaped in the meantime.
+        let reaped = self.reaped.lock().unwrap();
+        if *reaped {
+            log::trace!("not killing reaped job [pgid={pid}]", pid = self.pid);
+            return;
+        }
+        kill_process_group(self.pid);
+    }
+
+    /// Makes sure that the process group won't be killed from now on. Called right before the
+    /// process is reaped.
+    pub fn set_reaped(&self) {
+        *self.reaped.lock().unwrap() = true;
+    }
+}
+
+pub fn kill_process_group(pid: Pid) {
+    // NOTE: Because we called process_group on the subprocess, its pid == its pgid.
+    log::info!("killing job [pgid={pid}]");
+    let errno = Errno::from(unsafe { nix::libc::killpg(pid.as_raw(), nix::libc::SIGKILL) });
+    if errno.is_error() {
+        log::trace!("failed to kill job [pid={pid}, error={errno}]");
+    }
+}
+
+/// Reaps `child` once it exits. `disarm` runs after the child has exited but before it is reaped,
+/// which is the last moment at which its process group may be killed: once the child is reaped, its
+/// pgid may be reused by an unrelated process group.
+pub fn reap(child: &mut Child, disarm: impl FnOnce()) -> std::io::Result<ExitStatus> {
+    let pid = Pid::from_raw(child.id() as i32);
+    loop {
+        // WNOWAIT leaves the child to be reaped below.
+        match waitid(Id::Pid(pid), WaitPidFlag::WEXITED | WaitPidFlag::WNOWAIT) {
+            Err(nix::errno::Errno::EINTR) => continue,
+            Err(error) => {
+                log::warn!("failed to wait for job [pid={pid}, error={error}]");
+                break;
+            }
+            Ok(_) => break,
+        }
+    }
+    disarm();
+    child.wait()
+}
+
+/// Kills a process group if it is still running once a timeout elapses. The watchdog must be
+/// disarmed before the process is reaped (see `reap`). Dropping the watchdog also disarms it.
+pub struct Watchdog {
+    _disconnect: Sender<()>,
+    state: Arc<Mutex<WatchdogState>>,
+}
+
+#[derive(Default)]
+struct WatchdogState {
+    disarmed: bool,
+    fired: bool,
+}
+
+impl Watchdog {
+    pub fn spawn(pid: Pid, timeout: Duration) -> Self {
+        let (disconnect, disconnected) = crossbeam_channel::bounded::<()>(1);
+        let state = Arc::new(Mutex::new(WatchdogState::default()));
+        std::thread::spawn({
+            let state = state.clone();
+            move || {
+                // Nothing is ever sent, the channel disconnects when the watchdog is dropped.
+                if let Err(RecvTimeoutError::Timeout) = disconnected.recv_timeout(timeout) {
+                    // Holding the lock while killing keeps `disarm` from returning (and the
+                    // process from being reaped) in the meantime.
+                    let mut state = state.lock().unwrap();
+                    if !state.disarmed {
+                        log::warn!("job timed out after {timeout:?} [pgid={pid}]");
+                        state.fired = true;
+                        kill_process_group(pid);
+                    }
+                }
+            }
+        });
+        Self {
+            _disconnect: disconnect,
+            state,
+        }
+    }
+
+    /// Makes sure that the process group won't be killed from now on.
+    pub fn disarm(&self) {
+        self.state.lock().unwrap().disarmed = true;
+    }
+
+    /// Whether the timeout elapsed and the process group was killed.
+    pub fn fired(&self) -> bool {
+        self.state.lock().unwrap().fired
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.disarm();
+    }
+}
+
+#[test]
+fn test_watchdog() {
+    use std::os::unix::process::CommandExt as _;
+
+    let spawn = |program: &str, args: &[&str], timeout: Duration| {
+        let child = Command::new(program)
+            .args(args)
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let watchdog = Watchdog::spawn(Pid::from_raw(child.id() as i32), timeout);
+        (child, watchdog)
+    };
+
+    let (mut child, watchdog) = spawn("sleep", &["30"], Duration::from_millis(50));
+    assert!(!reap(&mut child, || watchdog.disarm()).unwrap().success());
+    assert!(watchdog.fired());
+
+    // Once disarmed, the watchdog leaves the process group alone.
+    let (mut child, watchdog) = spawn("true", &[], Duration::from_secs(30));
+    assert!(reap(&mut child, || watchdog.disarm()).unwrap().success());
+    assert!(!watchdog.fired());
+}
+
+#[test]
+fn test_process_group() {
+    use std::os::unix::process::CommandExt as _;
+
+    let spawn = || {
+        let child = Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let process_group = ProcessGroup::new(Pid::from_raw(child.id() as i32));
+        (child, process_group)
+    };
+
+    let (mut child, process_group) = spawn();
+    process_group.kill();
+    assert!(!child.wait().unwrap().success());
+
+    // Once the process is about to be reaped, its process group is left alone.
+    let (mut child, process_group) = spawn();
+    process_group.set_reaped();
+    process_group.kill();
+    assert!(child.try_wait().unwrap().is_none());
+    kill_process_group(process_group.pid());
+    child.wait().unwrap();
 }
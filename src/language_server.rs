@@ -6,15 +6,45 @@ use serde::de::{self, Deserialize, Deserializer, Visitor};
 use crate::prelude::*;
 
 pub trait LanguageServer {
-    fn code_action(&mut self, params: CodeActionParams) -> Result<Option<CodeActionResponse>>;
+    fn code_action(
+        &mut self,
+        params: CodeActionParams,
+        cancellation: &CancellationToken,
+    ) -> Result<Option<CodeActionResponse>>;
     fn did_change(&mut self, params: DidChangeTextDocumentParams) -> Result<()>;
     fn did_change_configuration(&mut self, dccp: DidChangeConfigurationParams) -> Result<()>;
     fn did_close(&mut self, params: DidCloseTextDocumentParams) -> Result<()>;
     fn did_open(&mut self, params: DidOpenTextDocumentParams) -> Result<()>;
     fn will_save(&mut self, params: WillSaveTextDocumentParams) -> Result<()>;
-    // fn did_save(&mut self, params: DidSaveTextDocumentParams) -> Result<()>;
-    fn execute_command(&mut self, params: ExecuteCommandParams) -> Result<Option<Value>>;
-    fn formatting(&mut self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>>;
+    fn did_save(&mut self, params: DidSaveTextDocumentParams) -> Result<()>;
+    fn document_diagnostic(
+        &mut self,
+        params: DocumentDiagnosticParams,
+    ) -> Result<DocumentDiagnosticReportResult>;
+    fn workspace_diagnostic(
+        &mut self,
+        params: WorkspaceDiagnosticParams,
+    ) -> Result<WorkspaceDiagnosticReportResult>;
+    fn execute_command(
+        &mut self,
+        params: ExecuteCommandParams,
+        cancellation: &CancellationToken,
+    ) -> Result<Option<Value>>;
+    fn formatting(
+        &mut self,
+        params: DocumentFormattingParams,
+        cancellation: &CancellationToken,
+    ) -> Result<Option<Vec<TextEdit>>>;
+    fn range_formatting(
+        &mut self,
+        params: DocumentRangeFormattingParams,
+        cancellation: &CancellationToken,
+    ) -> Result<Option<Vec<TextEdit>>>;
+    fn will_save_wait_until(
+        &mut self,
+        params: WillSaveTextDocumentParams,
+        cancellation: &CancellationToken,
+    ) -> Result<Option<Vec<TextEdit>>>;
     fn initialize(&mut self, params: InitializeParams) -> Result<InitializeResult>;
     fn initialized(&mut self, _: InitializedParams) -> Result<()>;
     fn shutdown(&self) -> Result<()>;
@@ -22,8 +52,10 @@ pub trait LanguageServer {
     fn workspace_symbol(
         &mut self,
         params: WorkspaceSymbolParams,
+        cancellation: &CancellationToken,
     ) -> Result<Option<Vec<SymbolInformation>>>;
     // fn exit(&self);
+    fn on_background_message(&mut self, message: BackgroundMessage) -> Result<()>;
 }
 
 pub struct ParseJsonRpc<R: BufRead> {
@@ -41,7 +73,7 @@ impl<R: BufRead> ParseJsonRpc<R> {
 }
 
 /// JSON-RPC 2.0 message ID
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
 pub enum MessageId {
     Number(i64),
     String(String),
@@ -150,8 +182,12 @@ impl JsonRpcResponse<()> {
             jsonrpc: "2.0",
             id,
             error: Some(JsonRpcError {
-                // -32000 to -32099 are reserved for implementation-defined server errors.
-                code: -32023,
+                code: if error.is::<RequestCancelled>() {
+                    REQUEST_CANCELLED
+                } else {
+                    // -32000 to -32099 are reserved for implementation-defined server errors.
+                    -32023
+                },
                 message: error.to_string(),
                 data: None,
             }),
@@ -166,9 +202,13 @@ pub struct JsonRpc {
     pub jsonrpc: String,
     #[serde(default)]
     pub id: Option<MessageId>,
+    /// Empty for responses to requests that the server sent to the client.
+    #[serde(default)]
     pub method: String,
     #[serde(default)]
     pub params: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<serde_json::Value>,
 }
 
 impl JsonRpc {
@@ -1,5 +1,7 @@
 // src/main.rs
 #![allow(clippy::too_many_arguments)]
+// `Uri` caches parts of itself in cells that don't affect its hash, so it is fine as a key.
+#![allow(clippy::mutable_key_type)]
 
 use std::rc::Rc;
 
@@ -9,7 +11,10 @@ use crate::prelude::*;
 extern crate serde_json;
 
 mod ai;
+mod background;
+mod cancellation;
 mod client;
+mod code_actions;
 mod config;
 mod diagnostic;
 mod diagnostic_severity;
@@ -18,12 +23,17 @@ mod document_diagnostics;
 mod document_storage;
 mod document_version;
 mod errno;
+mod errorformat;
 mod job;
 mod language_server;
+mod position_encoding;
 mod prelude;
 mod progress;
 mod server;
+mod structured_output;
 mod tags;
+mod text_edits;
+mod tmpfile;
 mod tool;
 mod utils;
 mod workspace;
@@ -31,25 +41,42 @@ mod workspace;
 struct PicklsBackend {
     client: Client,
     rt: Runtime,
+    background_sender: Sender<BackgroundMessage>,
     client_info: Option<ClientInfo>,
+    /// How the characters of positions are counted, as negotiated during `initialize`.
+    position_encoding: PositionEncoding,
 
     workspace: Workspace,
     jobs: HashMap<JobId, Vec<Job>>,
+    workspace_jobs: HashMap<WorkspaceJobId, ProcessGroup>,
     document_storage: HashMap<Uri, DocumentStorage>,
     config: PicklsConfig,
     diagnostics_manager: DiagnosticsManager,
+    /// Whether the client lets us create progress tokens (via `window/workDoneProgress/create`).
+    work_done_progress: bool,
+    next_progress_id: u64,
 }
 
 impl PicklsBackend {
-    pub fn new(client: Client, rt: Runtime, config: PicklsConfig) -> Self {
+    pub fn new(
+        client: Client,
+        background_sender: Sender<BackgroundMessage>,
+        rt: Runtime,
+        config: PicklsConfig,
+    ) -> Self {
         Self {
             rt,
+            background_sender,
             workspace: Workspace::new(),
             config,
             jobs: Default::default(),
+            workspace_jobs: Default::default(),
             client_info: None,
+            position_encoding: PositionEncoding::default(),
             document_storage: Default::default(),
             diagnostics_manager: DiagnosticsManager::new(client.clone()),
+            work_done_progress: false,
+            next_progress_id: 1,
             client,
         }
     }
@@ -93,6 +120,304 @@ impl PicklsBackend {
         )
     }
 
+    fn inline_assist_code_actions(
+        &mut self,
+        params: CodeActionParams,
+        cancellation: &CancellationToken,
+    ) -> Result<Option<CodeActionResponse>> {
+        // Get the text of the document from the document storage.
+        let uri = params.text_document.uri;
+        let DocumentStorage {
+            language_id,
+            file_contents,
+            ..
+        } = self.get_document(&uri)?;
+        let range: Range = params.range;
+        let file_contents = file_contents.as_ref();
+        let text = slice_range(file_contents, range, self.position_encoding);
+        log::trace!("Got a selection: {text} [range={range:#?}]");
+        if text.is_empty() {
+            log::info!("No selection found, returning early");
+            return Ok(None);
+        }
+
+        let total_inline_assistants = self.config.ai.inline_assistants.len();
+
+        // Report progress under the token that the client passed along with the request, or else
+        // one created for it. Clients may offer to cancel it, which cancels the request.
+        let progress_token = match params.work_done_progress_params.work_done_token {
+            Some(progress_token) => Some(progress_token),
+            None if self.work_done_progress => {
+                let progress_token = ProgressToken::String(format!(
+                    "pickls/inline-assist/{}",
+                    self.next_progress_id
+                ));
+                self.next_progress_id += 1;
+                self.client
+                    .send_request::<lsp_types::request::WorkDoneProgressCreate>(
+                        WorkDoneProgressCreateParams {
+                            token: progress_token.clone(),
+                        },
+                    )?;
+                Some(progress_token)
+            }
+            None => None,
+        };
+        if let Some(progress_token) = &progress_token {
+            cancellation.set_progress_token(progress_token.clone());
+        }
+        let progress_notifier = Rc::new(ProgressNotifier::begin(
+            self.client.clone(),
+            progress_token,
+            "running inline-assist",
+            total_inline_assistants,
+        )?);
+
+        let result = (|| {
+            Ok(Some(
+                // Iterate over all of the inline assistants and collect the results.
+                self.fetch_inline_assistance(
+                    language_id,
+                    text,
+                    progress_notifier.clone(),
+                    cancellation,
+                )?
+                .context("no inline assistants found")?
+                .into_iter()
+                .map(|response| {
+                    CodeActionOrCommand::CodeAction(CodeAction {
+                        title: format!(
+                            "Pickls Inline Assist ({} - {})",
+                            response.provider, response.model
+                        ),
+                        kind: Some(CodeActionKind::new("pickls.inline-assist")),
+                        edit: Some(WorkspaceEdit {
+                            changes: Some(
+                                [(
+                                    uri.clone(),
+                                    vec![TextEdit {
+                                        range,
+                                        new_text: response.code,
+                                    }],
+                                )]
+                                .into_iter()
+                                .collect(),
+                            ),
+                            document_changes: None,
+                            change_annotations: None,
+                        }),
+                        command: None,
+                        diagnostics: None,
+                        is_preferred: None,
+                        disabled: None,
+                        data: None,
+                    })
+                })
+                .collect(),
+            ))
+        })();
+        progress_notifier.end()?;
+        result
+    }
+
+    /// Runs the fixer named `fixer_name` on a document and asks the client to apply the result.
+    fn run_fixer(
+        &mut self,
+        uri: Uri,
+        fixer_name: &str,
+        cancellation: &CancellationToken,
+    ) -> Result<()> {
+        let DocumentStorage {
+            language_id,
+            file_contents,
+            version,
+        } = self.get_document(&uri)?;
+        let language_config = self
+            .fetch_language_config(&language_id)
+            .with_context(|| format!("no language config found for language ID {language_id:?}"))?;
+        let fixer = language_config
+            .fixers
+            .iter()
+            .find(|fixer| fixer.name() == fixer_name)
+            .with_context(|| format!("no fixer named `{fixer_name}` for {language_id}"))?;
+        let fixed_contents = run_formatter(
+            &fixer.formatter,
+            &self.workspace,
+            file_contents.clone(),
+            uri.clone(),
+            &language_config.root_markers,
+            None,
+            self.position_encoding,
+            cancellation,
+        )
+        .inspect_err(|error| {
+            if error.is::<RequestCancelled>() {
+                return;
+            }
+            let _ = self.client.show_message(
+                MessageType::ERROR,
+                format!(
+                    "Fixer {fixer_name} failed for url '{uri}' [error={error:?}]",
+                    uri = uri.as_str()
+                ),
+            );
+        })?;
+        if fixed_contents == file_contents {
+            log::info!(
+                "fixer {fixer_name} made no changes to '{uri}'",
+                uri = uri.as_str()
+            );
+            return Ok(());
+        }
+        self.client
+            .send_request::<lsp_types::request::ApplyWorkspaceEdit>(ApplyWorkspaceEditParams {
+                label: Some(format!("Fix all with {fixer_name}")),
+                edit: WorkspaceEdit {
+                    changes: None,
+                    // The edits are versioned, so that the client rejects them if the document
+                    // changed while the fixer ran.
+                    document_changes: Some(DocumentChanges::Edits(vec![TextDocumentEdit {
+                        text_document: OptionalVersionedTextDocumentIdentifier {
+                            uri,
+                            version: Some(version.0),
+                        },
+                        edits: compute_text_edits(
+                            &file_contents,
+                            &fixed_contents,
+                            self.position_encoding,
+                        )
+                        .into_iter()
+                        .map(OneOf::Left)
+                        .collect(),
+                    }])),
+                    change_annotations: None,
+                },
+            })
+    }
+
+    /// Runs the formatters of a document in order and returns the edits that apply their result.
+    /// When formatting a `range`, each formatter gets the range as remapped through the edits of
+    /// the formatters before it, and only the edits that touch the range are kept, since
+    /// formatters without `range_args` format the whole document (and others may stray).
+    /// Formatters are killed once `deadline` passes, and formatters that haven't started by then
+    /// are skipped. Cancelling the request kills the running formatter and skips the rest.
+    fn format_document(
+        &mut self,
+        uri: Uri,
+        range: Option<Range>,
+        deadline: Option<Instant>,
+        cancellation: &CancellationToken,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        let DocumentStorage {
+            mut file_contents,
+            language_id,
+            ..
+        } = self.get_document(&uri)?;
+        let original_contents = file_contents.clone();
+        let mut formatted_range = range;
+        let language_config = match self.fetch_language_config(&language_id) {
+            Some(config) => config,
+            None => {
+                log::info!("No language config found for language ID {language_id:?}");
+                return Ok(None);
+            }
+        };
+
+        let formatters = select_formatters(
+            &language_config.formatters,
+            uri.path().as_str(),
+            &self.workspace,
+        );
+        log::warn!(
+            "Formatting file '{uri}' with {count} formatters",
+            uri = uri.as_str(),
+            count = formatters.len()
+        );
+        for mut formatter_config in formatters.iter().cloned() {
+            cancellation.check()?;
+            let program = formatter_config.program.clone();
+            if let Some(deadline) = deadline {
+                let remaining_ms = deadline
+                    .saturating_duration_since(Instant::now())
+                    .as_millis() as u64;
+                if remaining_ms == 0 {
+                    log::warn!(
+                        "Skipping formatter {program} for url '{uri}' because the time budget ran out",
+                        uri = uri.as_str()
+                    );
+                    break;
+                }
+                formatter_config.timeout_ms = Some(
+                    formatter_config
+                        .timeout_ms
+                        .map_or(remaining_ms, |timeout_ms| timeout_ms.min(remaining_ms)),
+                );
+            }
+            file_contents = match run_formatter(
+                &formatter_config,
+                &self.workspace,
+                file_contents.clone(),
+                uri.clone(),
+                &language_config.root_markers,
+                formatted_range,
+                self.position_encoding,
+                cancellation,
+            )
+            .inspect(|formatted_content| {
+                log::info!(
+                    "Formatter {program} succeeded for url '{uri}' \
+                        [formatted_len={formatted_len}, formatter={program}]",
+                    uri = uri.as_str(),
+                    formatted_len = formatted_content.len(),
+                );
+            })
+            .context("formatter error")
+            {
+                Ok(formatted_contents) => {
+                    formatted_range = formatted_range.map(|formatted_range| {
+                        remap_range(
+                            &file_contents,
+                            &formatted_contents,
+                            formatted_range,
+                            self.position_encoding,
+                        )
+                    });
+                    formatted_contents
+                }
+                Err(e) if e.is::<RequestCancelled>() => return Err(e),
+                Err(e) => {
+                    self.client
+                        .show_message(
+                            MessageType::ERROR,
+                            format!(
+                                "Formatter {program} failed for url '{uri}' [error={e:?}]",
+                                uri = uri.as_str(),
+                                program = program,
+                                e = e
+                            ),
+                        )
+                        .unwrap();
+                    log::error!(
+                        "Formatter {program} failed for url '{uri}' [error={e:?}]",
+                        uri = uri.as_str(),
+                        program = program,
+                        e = e
+                    );
+                    match formatter_config.on_error {
+                        PicklsFormatterOnError::Skip => continue,
+                        PicklsFormatterOnError::Abort => return Ok(None),
+                    }
+                }
+            };
+        }
+        let mut edits =
+            compute_text_edits(&original_contents, &file_contents, self.position_encoding);
+        if let Some(range) = range {
+            edits.retain(|edit| edit.range.start <= range.end && range.start <= edit.range.end);
+        }
+        Ok(Some(edits))
+    }
+
     fn get_document(&self, uri: &Uri) -> Result<DocumentStorage> {
         match self.document_storage.get(uri).cloned() {
             Some(ds) => Ok(ds),
@@ -103,7 +428,7 @@ impl PicklsBackend {
         }
     }
 
-    fn run_diagnostics(&mut self, job_spec: JobSpec) -> Result<()> {
+    fn run_diagnostics(&mut self, job_spec: JobSpec, event: PicklsRunOn) -> Result<()> {
         // Get a copy of the tool configuration for future use. Bail out if we
         // can't find it, this just means that the user doesn't want us to
         // run diagnostics for this language.
@@ -116,48 +441,293 @@ impl PicklsBackend {
         };
 
         let job_id = JobId::from(&job_spec);
-        // Get rid of a prior running jobs.
-        if let Some(jobs) = self.jobs.remove(&job_id) {
-            for job in jobs {
+        // Get rid of prior running jobs that this event is about to replace. Jobs of linters that
+        // this event does not trigger (ie: a slow on-save linter during a burst of changes) keep
+        // running.
+        if let Some(jobs) = self.jobs.get_mut(&job_id) {
+            for job in jobs.extract_if(.., |job| job.run_on.is_triggered_by(event)) {
                 job.spawn_kill();
             }
         }
 
-        let mut new_jobs: Vec<Job> = Default::default();
-        let max_linter_count = language_config.linters.len();
-
-        for linter_config in language_config.linters {
-            let job_id: JobId = job_id.clone();
-            let job_spec: JobSpec = job_spec.clone();
-            let file_content = if linter_config.use_stdin {
-                Some(job_spec.text.clone())
-            } else {
-                None
+        let active_linters = self.active_linters(&language_config, &job_spec.uri);
+        let max_linter_count = document_linter_count(&language_config, &active_linters);
+        for linter_index in active_linters {
+            let linter_config = &language_config.linters[linter_index];
+            if linter_config.scope == PicklsLinterScope::Workspace {
+                self.start_workspace_linter(
+                    &job_spec,
+                    &language_config,
+                    linter_config.clone(),
+                    event,
+                )?;
+                continue;
+            }
+            if !linter_config.run_on.is_triggered_by(event) {
+                continue;
+            }
+            // Only changes are debounced. Opening and saving are deliberate, one-off events.
+            let debounce_ms = match event {
+                PicklsRunOn::Change => linter_config
+                    .debounce_ms
+                    .or(language_config.debounce_ms)
+                    .unwrap_or_default(),
+                PicklsRunOn::Save | PicklsRunOn::Open => 0,
             };
-            let pid: Pid = run_linter(
-                &mut self.diagnostics_manager,
-                linter_config,
-                &self.workspace,
-                max_linter_count,
-                file_content,
-                job_spec.uri.clone(),
-                job_spec.version,
-                &language_config.root_markers,
-            )?;
-            debug_assert!(!self.jobs.contains_key(&job_id));
-            new_jobs.push(Job { pid });
+            if debounce_ms == 0 {
+                self.start_linter(
+                    &job_spec,
+                    &language_config,
+                    linter_config.clone(),
+                    max_linter_count,
+                )?;
+                continue;
+            }
+            // Wait for the burst of changes to settle down. If another version of the document
+            // arrives in the meantime, this wakeup will find that it is stale and do nothing.
+            let background_sender = self.background_sender.clone();
+            let uri = job_spec.uri.clone();
+            let version = job_spec.version;
+            self.rt.spawn(async move {
+                tokio::time::sleep(Duration::from_millis(debounce_ms)).await;
+                let _ = background_sender.send(BackgroundMessage::DebounceElapsed {
+                    uri,
+                    version,
+                    linter_index,
+                });
+            });
         }
+        Ok(())
+    }
+
+    /// Returns the indices of the linters whose `when` conditions hold for `uri`.
+    fn active_linters(&self, language_config: &PicklsLanguageConfig, uri: &Uri) -> Vec<usize> {
+        language_config
+            .linters
+            .iter()
+            .enumerate()
+            .filter(|(_, linter_config)| {
+                conditions_hold(
+                    &linter_config.when,
+                    &linter_config.program,
+                    uri.path().as_str(),
+                    &self.workspace,
+                )
+            })
+            .map(|(linter_index, _)| linter_index)
+            .collect()
+    }
 
+    fn start_linter(
+        &mut self,
+        job_spec: &JobSpec,
+        language_config: &PicklsLanguageConfig,
+        linter_config: PicklsLinterConfig,
+        max_linter_count: usize,
+    ) -> Result<()> {
+        let job_id = JobId::from(job_spec);
+        let run_on = linter_config.run_on;
+        let process_group = run_linter(
+            &self.rt,
+            self.background_sender.clone(),
+            job_id.clone(),
+            linter_config,
+            &self.workspace,
+            max_linter_count,
+            job_spec.text.clone(),
+            job_spec.uri.clone(),
+            job_spec.version,
+            &language_config.root_markers,
+            language_config.min_severity,
+            self.position_encoding,
+        )?;
         // Remember which jobs we started.
-        assert!(self.jobs.insert(job_id, new_jobs).is_none());
+        self.jobs.entry(job_id).or_default().push(Job {
+            process_group,
+            run_on,
+        });
+        Ok(())
+    }
+
+    /// Runs a workspace-scoped linter over the project of a document. These read the project from
+    /// disk, so they run when a document is saved rather than as it changes. Opening a document
+    /// only runs them if they haven't run for its project yet.
+    fn start_workspace_linter(
+        &mut self,
+        job_spec: &JobSpec,
+        language_config: &PicklsLanguageConfig,
+        linter_config: PicklsLinterConfig,
+        event: PicklsRunOn,
+    ) -> Result<()> {
+        let triggered = match event {
+            PicklsRunOn::Change => false,
+            PicklsRunOn::Save => linter_config.run_on != PicklsRunOn::Open,
+            PicklsRunOn::Open => true,
+        };
+        if !triggered {
+            return Ok(());
+        }
+        let job_id = WorkspaceJobId {
+            linter_name: linter_config.program.clone(),
+            root_dir: linter_root_dir(
+                &linter_config,
+                &self.workspace,
+                job_spec.uri.path().as_str(),
+                &language_config.root_markers,
+            )?,
+        };
+        if event == PicklsRunOn::Open
+            && (self.workspace_jobs.contains_key(&job_id)
+                || self.diagnostics_manager.has_workspace_report(&job_id))
+        {
+            log::trace!("workspace linter has already run, skipping [job_id={job_id}]");
+            return Ok(());
+        }
+        // The new run supersedes any run of the same project that is still going.
+        if let Some(process_group) = self.workspace_jobs.remove(&job_id) {
+            process_group.kill();
+        }
+        let open_documents: OpenDocuments = self
+            .document_storage
+            .iter()
+            .filter_map(|(uri, document)| {
+                let path = realpath_for_uri(uri).ok()?;
+                path.starts_with(&job_id.root_dir)
+                    .then(|| (path, (uri.clone(), document.file_contents.clone())))
+            })
+            .collect();
+        let process_group = run_workspace_linter(
+            &self.rt,
+            self.background_sender.clone(),
+            job_id.clone(),
+            linter_config,
+            &self.workspace,
+            job_spec.text.clone(),
+            job_spec.uri.clone(),
+            open_documents,
+            &language_config.root_markers,
+            language_config.min_severity,
+            self.position_encoding,
+        )?;
+        self.workspace_jobs.insert(job_id, process_group);
         Ok(())
     }
 
+    fn on_debounce_elapsed(
+        &mut self,
+        uri: Uri,
+        version: DocumentVersion,
+        linter_index: usize,
+    ) -> Result<()> {
+        let Some(document) = self.document_storage.get(&uri) else {
+            log::trace!("document was closed before debounce elapsed, skipping");
+            return Ok(());
+        };
+        if document.version != version {
+            log::trace!(
+                "debounced lint of version {version} is stale [current={current}]",
+                current = document.version
+            );
+            return Ok(());
+        }
+        let job_spec = JobSpec {
+            uri,
+            version,
+            language_id: document.language_id.clone(),
+            text: document.file_contents.clone(),
+        };
+        let Some(language_config) = self.fetch_language_config(&job_spec.language_id) else {
+            return Ok(());
+        };
+        // The configuration (or the files that the linter's conditions depend on) may have changed
+        // while we were waiting.
+        let active_linters = self.active_linters(&language_config, &job_spec.uri);
+        if !active_linters.contains(&linter_index) {
+            return Ok(());
+        }
+        let Some(linter_config) = language_config.linters.get(linter_index).cloned() else {
+            return Ok(());
+        };
+        self.start_linter(
+            &job_spec,
+            &language_config,
+            linter_config,
+            document_linter_count(&language_config, &active_linters),
+        )
+    }
+
+    fn on_linter_finished(&mut self, output: LinterOutput) -> Result<()> {
+        let LinterOutput {
+            job_id,
+            pid,
+            linter_name,
+            run_on,
+            max_linter_count,
+            uri,
+            version,
+            diagnostics,
+        } = output;
+        // Only jobs that are still tracked get to report. Anything else was killed because a newer
+        // run superseded it, and its (likely truncated) output is of no use.
+        let Some(jobs) = self.jobs.get_mut(&job_id) else {
+            log::info!("dropping output of superseded linter {linter_name} [pid={pid}]");
+            return Ok(());
+        };
+        let Some(index) = jobs.iter().position(|job| job.process_group.pid() == pid) else {
+            log::info!("dropping output of superseded linter {linter_name} [pid={pid}]");
+            return Ok(());
+        };
+        jobs.swap_remove(index);
+        if jobs.is_empty() {
+            self.jobs.remove(&job_id);
+        }
+        let diagnostics = diagnostics.unwrap_or_else(|error| {
+            log::error!("linter {linter_name} failed [pid={pid}, error={error:?}]");
+            // Report the failure in place of the linter's diagnostics, so that its stale
+            // diagnostics go away and the progress of this version still completes.
+            vec![linter_failure_diagnostic(&linter_name, &error)]
+        });
+        self.diagnostics_manager.update_diagnostics(
+            uri,
+            linter_name,
+            run_on,
+            max_linter_count,
+            version,
+            diagnostics,
+        )
+    }
+
+    fn on_workspace_linter_finished(&mut self, output: WorkspaceLinterOutput) -> Result<()> {
+        let WorkspaceLinterOutput {
+            job_id,
+            pid,
+            uri,
+            diagnostics,
+        } = output;
+        if self.workspace_jobs.get(&job_id).map(ProcessGroup::pid) != Some(pid) {
+            log::info!(
+                "dropping output of superseded workspace linter [job_id={job_id}, pid={pid}]"
+            );
+            return Ok(());
+        }
+        self.workspace_jobs.remove(&job_id);
+        let diagnostics = diagnostics.unwrap_or_else(|error| {
+            log::error!("workspace linter failed [job_id={job_id}, pid={pid}, error={error:?}]");
+            // Like a timeout, the failure is reported on the document that triggered the run, and
+            // replaces the diagnostics of the previous run everywhere else.
+            let diagnostic = linter_failure_diagnostic(&job_id.linter_name, &error);
+            HashMap::from([(uri, vec![diagnostic])])
+        });
+        self.diagnostics_manager
+            .update_workspace_diagnostics(job_id, diagnostics)
+    }
+
     fn fetch_inline_assistance(
         &self,
         language_id: String,
         text: String,
         progress_notifier: Rc<ProgressNotifier>,
+        cancellation: &CancellationToken,
     ) -> Result<Option<Vec<InlineAssistResponse>>> {
         if self.config.ai.inline_assistants.is_empty() {
             return Ok(None);
@@ -227,7 +797,11 @@ impl PicklsBackend {
                     })
                 })
                 .collect::<Vec<_>>();
-            let results = join_all(futures).await;
+            // Cancelling the request drops the requests to the assistants that are still running.
+            let results = tokio::select! {
+                results = join_all(futures) => results,
+                () = cancellation.cancelled() => return Err(RequestCancelled.into()),
+            };
             if results.is_empty() {
                 anyhow::bail!("All inline assistants failed");
             }
@@ -281,9 +855,52 @@ impl PicklsBackend {
 }
 
 impl LanguageServer for PicklsBackend {
+    fn on_background_message(&mut self, message: BackgroundMessage) -> Result<()> {
+        match message {
+            BackgroundMessage::LinterFinished(output) => self.on_linter_finished(output),
+            BackgroundMessage::WorkspaceLinterFinished(output) => {
+                self.on_workspace_linter_finished(output)
+            }
+            BackgroundMessage::DebounceElapsed {
+                uri,
+                version,
+                linter_index,
+            } => self.on_debounce_elapsed(uri, version, linter_index),
+        }
+    }
     fn initialize(&mut self, params: InitializeParams) -> Result<InitializeResult> {
         log::info!("[initialize called [pickls_pid={}]", std::process::id());
         self.client_info = params.client_info;
+        self.position_encoding = PositionEncoding::negotiate(&params.capabilities);
+        log::info!(
+            "negotiated position encoding [encoding={encoding:?}]",
+            encoding = self.position_encoding
+        );
+        // Diagnostics are pulled by clients that support it, as long as they also let us ask them
+        // to pull again once linters running in the background finish.
+        let pull_diagnostics = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .is_some_and(|text_document| text_document.diagnostic.is_some())
+            && params
+                .capabilities
+                .workspace
+                .as_ref()
+                .and_then(|workspace| workspace.diagnostic.as_ref())
+                .and_then(|diagnostic| diagnostic.refresh_support)
+                .unwrap_or(false);
+        log::info!(
+            "diagnostics will be {}",
+            if pull_diagnostics { "pulled" } else { "pushed" }
+        );
+        self.diagnostics_manager.set_pull(pull_diagnostics);
+        self.work_done_progress = params
+            .capabilities
+            .window
+            .as_ref()
+            .and_then(|window| window.work_done_progress)
+            .unwrap_or(false);
         if let Some(workspace_folders) = params.workspace_folders {
             for workspace_folder in workspace_folders {
                 log::info!(
@@ -302,24 +919,36 @@ impl LanguageServer for PicklsBackend {
         }
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                position_encoding: Some(self.position_encoding.kind()),
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        change: Some(TextDocumentSyncKind::INCREMENTAL),
+                        will_save: None,
+                        will_save_wait_until: Some(true),
+                        save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                    },
                 )),
-                diagnostic_provider: Some(DiagnosticServerCapabilities::Options(
-                    DiagnosticOptions {
+                diagnostic_provider: pull_diagnostics.then_some(
+                    DiagnosticServerCapabilities::Options(DiagnosticOptions {
                         identifier: None,
                         inter_file_dependencies: false,
-                        workspace_diagnostics: false,
+                        workspace_diagnostics: true,
                         work_done_progress_options: WorkDoneProgressOptions {
                             work_done_progress: Some(false),
                         },
-                    },
-                )),
+                    }),
+                ),
 
                 document_formatting_provider: Some(OneOf::Left(true)),
+                document_range_formatting_provider: Some(OneOf::Left(true)),
                 code_action_provider: Some(CodeActionProviderCapability::Options(
                     CodeActionOptions {
-                        code_action_kinds: Some(vec![CodeActionKind::new("pickls.inline-assist")]),
+                        code_action_kinds: Some(vec![
+                            CodeActionKind::QUICKFIX,
+                            CodeActionKind::SOURCE_FIX_ALL,
+                            CodeActionKind::new("pickls.inline-assist"),
+                        ]),
                         work_done_progress_options: WorkDoneProgressOptions {
                             work_done_progress: Some(false),
                         },
@@ -327,7 +956,7 @@ impl LanguageServer for PicklsBackend {
                     },
                 )),
                 execute_command_provider: Some(ExecuteCommandOptions {
-                    commands: vec!["pickls.inline-assist".to_string()],
+                    commands: vec!["pickls.inline-assist".to_string(), FIX_COMMAND.to_string()],
                     work_done_progress_options: WorkDoneProgressOptions {
                         work_done_progress: Some(false),
                     },
@@ -353,179 +982,134 @@ impl LanguageServer for PicklsBackend {
             }),
         })
     }
-    fn code_action(&mut self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+    fn code_action(
+        &mut self,
+        params: CodeActionParams,
+        cancellation: &CancellationToken,
+    ) -> Result<Option<CodeActionResponse>> {
         log::trace!("Got a textDocument/codeAction request: {params:#?}");
-        // Get the text of the document from the document storage.
-        if let Some(filter) = params.context.only
-            && !filter.contains(&CodeActionKind::new("pickls.inline-assist"))
-        {
-            log::trace!(
-                "Client is filtering code actions, no pickls.inline-assist found, returning early"
-            );
-            return Ok(None);
-        }
-        let uri = params.text_document.uri;
+        let only = params.context.only.as_deref();
+        let uri = &params.text_document.uri;
         let DocumentStorage {
             language_id,
             file_contents,
             version,
-        } = self.get_document(&uri)?;
-        let range: Range = params.range;
-        let file_contents = file_contents.as_ref();
-        let text = slice_range(file_contents, range);
-        log::trace!("Got a selection: {text} [range={range:#?}]");
-        if text.is_empty() {
-            log::info!("No selection found, returning early");
-            return Ok(None);
+        } = self.get_document(uri)?;
+        let linter_diagnostics = self.diagnostics_manager.linter_diagnostics(uri);
+        let mut actions = Vec::new();
+        if code_action_kind_requested(only, &CodeActionKind::QUICKFIX) {
+            actions.extend(linter_quick_fixes(
+                uri,
+                version,
+                &params.context.diagnostics,
+                linter_diagnostics,
+            ));
         }
-
-        let total_inline_assistants = self.config.ai.inline_assistants.len();
-
-        // Always create at least one progress message to denote the current update.
-        let progress = make_progress_params(
-            "running inline-assist",
-            uri.clone(),
-            version,
-            0,
-            total_inline_assistants,
-        );
-        self.client.send_notification::<Progress, _>(progress)?;
-
-        let progress_notifier = Rc::new(ProgressNotifier::new(
-            self.client.clone(),
-            uri.clone(),
-            version,
-            total_inline_assistants,
-        ));
-        let completed_progress = make_progress_params(
-            "completed inline-assist",
-            uri.clone(),
-            version,
-            total_inline_assistants,
-            total_inline_assistants,
-        );
-
-        let result = (|| {
-            Ok(Some(
-                // Iterate over all of the inline assistants and collect the results.
-                self.fetch_inline_assistance(language_id, text, progress_notifier)?
-                    .context("no inline assistants found")?
-                    .into_iter()
-                    .map(|response| {
-                        CodeActionOrCommand::CodeAction(CodeAction {
-                            title: format!(
-                                "Pickls Inline Assist ({} - {})",
-                                response.provider, response.model
-                            ),
-                            kind: Some(CodeActionKind::new("pickls.inline-assist")),
-                            edit: Some(WorkspaceEdit {
-                                changes: Some(
-                                    [(
-                                        uri.clone(),
-                                        vec![TextEdit {
-                                            range,
-                                            new_text: response.code,
-                                        }],
-                                    )]
-                                    .into_iter()
-                                    .collect(),
-                                ),
-                                document_changes: None,
-                                change_annotations: None,
-                            }),
-                            command: None,
-                            diagnostics: None,
-                            is_preferred: None,
-                            disabled: None,
-                            data: None,
-                        })
-                    })
-                    .collect(),
-            ))
-        })();
-        self.client
-            .send_notification::<Progress, _>(completed_progress)?;
-        result
+        if code_action_kind_requested(only, &CodeActionKind::SOURCE_FIX_ALL)
+            && let Some(linter_diagnostics) = linter_diagnostics
+        {
+            actions.extend(linter_fix_all_actions(uri, version, linter_diagnostics));
+        }
+        if let Some(language_config) = self.fetch_language_config(&language_id) {
+            if code_action_kind_requested(only, &CodeActionKind::QUICKFIX) {
+                actions.extend(suppression_actions(
+                    uri,
+                    &file_contents,
+                    &params.context.diagnostics,
+                    &language_config.linters,
+                    self.position_encoding,
+                ));
+            }
+            let fixers = language_config
+                .fixers
+                .iter()
+                .filter(|fixer| {
+                    conditions_hold(
+                        &fixer.formatter.when,
+                        &fixer.formatter.program,
+                        uri.path().as_str(),
+                        &self.workspace,
+                    )
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            actions.extend(fixer_actions(uri, only, &fixers));
+        }
+        if code_action_kind_requested(only, &CodeActionKind::new("pickls.inline-assist"))
+            && !self.config.ai.inline_assistants.is_empty()
+            && let Some(inline_assist_actions) =
+                self.inline_assist_code_actions(params, cancellation)?
+        {
+            actions.extend(inline_assist_actions);
+        }
+        Ok((!actions.is_empty()).then_some(actions))
     }
-    fn execute_command(&mut self, params: ExecuteCommandParams) -> Result<Option<Value>> {
-        let _ = params;
-        log::error!("Got a workspace/executeCommand request, but it is not implemented");
+    fn execute_command(
+        &mut self,
+        params: ExecuteCommandParams,
+        cancellation: &CancellationToken,
+    ) -> Result<Option<Value>> {
+        match params.command.as_str() {
+            FIX_COMMAND => {
+                let (uri, fixer_name): (Uri, String) =
+                    serde_json::from_value(Value::Array(params.arguments))
+                        .context("pickls.fix expects a uri and a fixer name")?;
+                self.run_fixer(uri, &fixer_name, cancellation)?;
+            }
+            command => {
+                log::error!("Got a workspace/executeCommand request for unknown command {command}");
+            }
+        }
         Ok(None)
     }
-    fn formatting(&mut self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+    fn formatting(
+        &mut self,
+        params: DocumentFormattingParams,
+        cancellation: &CancellationToken,
+    ) -> Result<Option<Vec<TextEdit>>> {
         log::info!("[formatting] called");
+        self.format_document(params.text_document.uri, None, None, cancellation)
+    }
+
+    fn range_formatting(
+        &mut self,
+        params: DocumentRangeFormattingParams,
+        cancellation: &CancellationToken,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        log::info!("[range_formatting] called");
+        self.format_document(
+            params.text_document.uri,
+            Some(params.range),
+            None,
+            cancellation,
+        )
+    }
 
+    fn will_save_wait_until(
+        &mut self,
+        params: WillSaveTextDocumentParams,
+        cancellation: &CancellationToken,
+    ) -> Result<Option<Vec<TextEdit>>> {
         let uri = params.text_document.uri;
-        let DocumentStorage {
-            mut file_contents,
-            language_id,
-            ..
-        } = self.get_document(&uri)?;
-        let language_config = match self.fetch_language_config(&language_id) {
-            Some(config) => config,
-            None => {
-                log::info!("No language config found for language ID {language_id:?}");
-                return Ok(None);
-            }
+        if params.reason == TextDocumentSaveReason::AFTER_DELAY {
+            // Don't rewrite the document out from under the user while they are typing.
+            log::info!("not formatting auto-saved '{uri}'", uri = uri.as_str());
+            return Ok(None);
+        }
+        let language_id = self.get_document(&uri)?.language_id;
+        let Some(language_config) = self.fetch_language_config(&language_id) else {
+            return Ok(None);
         };
-
-        // The big edit to return.
-        log::warn!(
-            "Formatting file '{uri}' with {count} formatters",
-            uri = uri.as_str(),
-            count = language_config.formatters.len()
-        );
-        for formatter_config in language_config.formatters.iter() {
-            let program = formatter_config.program.clone();
-            file_contents = match run_formatter(
-                formatter_config,
-                &self.workspace,
-                file_contents.clone(),
-                uri.clone(),
-                &language_config.root_markers,
-            )
-            .inspect(|formatted_content| {
-                log::info!(
-                    "Formatter {program} succeeded for url '{uri}' \
-                        [formatted_len={formatted_len}, formatter={program}]",
-                    uri = uri.as_str(),
-                    formatted_len = formatted_content.len(),
-                );
-
-                // Create a TextEdit that replaces the whole document
-            })
-            .context("formatter error")
-            {
-                Ok(file_contents) => file_contents,
-                Err(e) => {
-                    self.client
-                        .show_message(
-                            MessageType::ERROR,
-                            format!(
-                                "Formatter {program} failed for url '{uri}' [error={e:?}]",
-                                uri = uri.as_str(),
-                                program = program,
-                                e = e
-                            ),
-                        )
-                        .unwrap();
-                    log::error!(
-                        "Formatter {program} failed for url '{uri}' [error={e:?}]",
-                        uri = uri.as_str(),
-                        program = program,
-                        e = e
-                    );
-                    break;
-                }
-            };
+        if !language_config.format_on_save {
+            return Ok(None);
         }
-        Ok(Some(vec![TextEdit {
-            range: Range {
-                start: Position::new(0, 0),
-                end: Position::new(u32::MAX, u32::MAX),
-            },
-            new_text: file_contents,
-        }]))
+        log::info!(
+            "[will_save_wait_until] formatting '{uri}'",
+            uri = uri.as_str()
+        );
+        let deadline =
+            Instant::now() + Duration::from_millis(language_config.format_on_save_timeout_ms);
+        self.format_document(uri, None, Some(deadline), cancellation)
     }
 
     fn initialized(&mut self, _: InitializedParams) -> Result<()> {
@@ -555,12 +1139,19 @@ impl LanguageServer for PicklsBackend {
     }
 
     fn did_close(&mut self, params: DidCloseTextDocumentParams) -> Result<()> {
-        self.document_storage.remove(&params.text_document.uri);
+        let uri = params.text_document.uri;
+        self.document_storage.remove(&uri);
         log::info!(
             "[{site}] did_close called [params=...]",
             site = self.get_workspace_name()
         );
-        Ok(())
+        // Whatever the linters of a closed document have left to say is of no use anymore.
+        if let Some(jobs) = self.jobs.remove(&JobId(uri.clone())) {
+            for job in jobs {
+                job.spawn_kill();
+            }
+        }
+        self.diagnostics_manager.close_document(&uri)
     }
     fn did_open(&mut self, params: DidOpenTextDocumentParams) -> Result<()> {
         log::info!(
@@ -577,12 +1168,15 @@ impl LanguageServer for PicklsBackend {
                 version: DocumentVersion(params.text_document.version),
             },
         );
-        self.run_diagnostics(JobSpec {
-            uri: params.text_document.uri,
-            version: DocumentVersion(params.text_document.version),
-            language_id: params.text_document.language_id,
-            text: file_contents,
-        })
+        self.run_diagnostics(
+            JobSpec {
+                uri: params.text_document.uri,
+                version: DocumentVersion(params.text_document.version),
+                language_id: params.text_document.language_id,
+                text: file_contents,
+            },
+            PicklsRunOn::Open,
+        )
         .context("run_diagnostics")
     }
     fn will_save(&mut self, params: WillSaveTextDocumentParams) -> Result<()> {
@@ -593,16 +1187,64 @@ impl LanguageServer for PicklsBackend {
         );
         Ok(())
     }
-    fn did_change(&mut self, mut params: DidChangeTextDocumentParams) -> Result<()> {
+    fn did_save(&mut self, params: DidSaveTextDocumentParams) -> Result<()> {
+        log::info!(
+            "[{site}] did_save called [uri={uri}]",
+            site = self.get_workspace_name(),
+            uri = params.text_document.uri.as_str()
+        );
+        let uri = params.text_document.uri;
+        let DocumentStorage {
+            language_id,
+            file_contents,
+            version,
+        } = self.get_document(&uri)?;
+        self.run_diagnostics(
+            JobSpec {
+                uri,
+                version,
+                language_id,
+                text: file_contents,
+            },
+            PicklsRunOn::Save,
+        )
+        .context("did_save")
+    }
+    fn document_diagnostic(
+        &mut self,
+        params: DocumentDiagnosticParams,
+    ) -> Result<DocumentDiagnosticReportResult> {
+        log::trace!(
+            "[{site}] document_diagnostic called [uri={uri}, previous_result_id={previous_result_id:?}]",
+            site = self.get_workspace_name(),
+            uri = params.text_document.uri.as_str(),
+            previous_result_id = params.previous_result_id
+        );
+        Ok(self
+            .diagnostics_manager
+            .document_diagnostic_report(&params.text_document.uri, params.previous_result_id))
+    }
+    fn workspace_diagnostic(
+        &mut self,
+        params: WorkspaceDiagnosticParams,
+    ) -> Result<WorkspaceDiagnosticReportResult> {
+        log::trace!(
+            "[{site}] workspace_diagnostic called [previous_result_ids={count}]",
+            site = self.get_workspace_name(),
+            count = params.previous_result_ids.len()
+        );
+        Ok(self
+            .diagnostics_manager
+            .workspace_diagnostic_report(params.previous_result_ids))
+    }
+    fn did_change(&mut self, params: DidChangeTextDocumentParams) -> Result<()> {
         log::trace!(
             "[{site}] did_change called [params=...]",
             site = self.get_workspace_name()
         );
-        assert!(params.content_changes.len() == 1);
-        let file_contents = params.content_changes.remove(0).text;
         let uri = params.text_document.uri;
 
-        let language_id = {
+        let (language_id, file_contents) = {
             let Some(document_storage) = self.document_storage.get_mut(&uri) else {
                 self.client
                     .show_message(
@@ -614,21 +1256,29 @@ impl LanguageServer for PicklsBackend {
             };
 
             // Update the file contents.
-            document_storage.file_contents = file_contents.clone();
-            document_storage.language_id.clone()
+            document_storage.apply_content_changes(params.content_changes, self.position_encoding);
+            document_storage.version = DocumentVersion(params.text_document.version);
+            (
+                document_storage.language_id.clone(),
+                document_storage.file_contents.clone(),
+            )
         };
 
-        self.run_diagnostics(JobSpec {
-            uri,
-            version: DocumentVersion(params.text_document.version),
-            language_id,
-            text: file_contents,
-        })
+        self.run_diagnostics(
+            JobSpec {
+                uri,
+                version: DocumentVersion(params.text_document.version),
+                language_id,
+                text: file_contents,
+            },
+            PicklsRunOn::Change,
+        )
         .context("did_change")
     }
     fn workspace_symbol(
         &mut self,
         params: WorkspaceSymbolParams,
+        cancellation: &CancellationToken,
     ) -> Result<Option<Vec<SymbolInformation>>> {
         let ctags_timeout = {
             let config = &self.config;
@@ -661,6 +1311,7 @@ impl LanguageServer for PicklsBackend {
             .map(|s| s.to_string())
             .collect(),
             ctags_timeout,
+            cancellation,
         )
         .context("failed to find symbols")?;
 
@@ -674,6 +1325,31 @@ impl LanguageServer for PicklsBackend {
     }
 }
 
+/// Reports that a linter failed to run, in place of its diagnostics.
+fn linter_failure_diagnostic(linter_name: &str, error: &anyhow::Error) -> Diagnostic {
+    Diagnostic {
+        range: Range::default(),
+        severity: Some(DiagnosticSeverity::WARNING),
+        source: Some(format!("[pickls/{linter_name}]")),
+        message: format!("{linter_name} failed: {error:#}"),
+        ..Default::default()
+    }
+}
+
+/// The number of `active_linters` that lint documents on their own. The progress of linting a
+/// document only counts these.
+fn document_linter_count(
+    language_config: &PicklsLanguageConfig,
+    active_linters: &[usize],
+) -> usize {
+    active_linters
+        .iter()
+        .filter(|&&linter_index| {
+            language_config.linters[linter_index].scope == PicklsLinterScope::Document
+        })
+        .count()
+}
+
 fn update_configuration(
     client: &Client,
     pickls_settings: &mut PicklsConfig,
@@ -740,7 +1416,10 @@ fn main() -> Result<()> {
     let config = read_config(&base_dirs).context("failed to read configuration")?;
     let rt = Runtime::new().context("creating tokio runtime")?;
     // Initialize the configuration's site name.
-    run_server(|client| PicklsBackend::new(client, rt, config)).inspect_err(|e| {
+    run_server(|client, background_sender| {
+        PicklsBackend::new(client, background_sender, rt, config)
+    })
+    .inspect_err(|e| {
         log::error!("pickls server exited with error: {e:?}");
     })
 }
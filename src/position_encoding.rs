@@ -0,0 +1,128 @@
+use crate::prelude::*;
+
+/// How the `character` of an LSP position counts the characters of its line. The encoding is
+/// negotiated with the client during `initialize`, and defaults to UTF-16 as the protocol requires.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PositionEncoding {
+    /// Bytes of UTF-8.
+    Utf8,
+    /// UTF-16 code units.
+    #[default]
+    Utf16,
+    /// Unicode code points.
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Picks the first of the client's `general.positionEncodings` that we support. Clients list
+    /// them in order of preference.
+    pub fn negotiate(capabilities: &ClientCapabilities) -> Self {
+        capabilities
+            .general
+            .as_ref()
+            .and_then(|general| general.position_encodings.as_ref())
+            .into_iter()
+            .flatten()
+            .find_map(|kind| match kind.as_str() {
+                "utf-8" => Some(Self::Utf8),
+                "utf-16" => Some(Self::Utf16),
+                "utf-32" => Some(Self::Utf32),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            Self::Utf8 => PositionEncodingKind::UTF8,
+            Self::Utf16 => PositionEncodingKind::UTF16,
+            Self::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    /// The number of units that `ch` takes up.
+    pub fn char_len(self, ch: char) -> u32 {
+        match self {
+            Self::Utf8 => ch.len_utf8() as u32,
+            Self::Utf16 => ch.len_utf16() as u32,
+            Self::Utf32 => 1,
+        }
+    }
+
+    /// The number of units that `text` takes up.
+    pub fn text_len(self, text: &str) -> u32 {
+        match self {
+            Self::Utf8 => text.len() as u32,
+            Self::Utf16 => text.encode_utf16().count() as u32,
+            Self::Utf32 => text.chars().count() as u32,
+        }
+    }
+
+    /// Converts a `column` of `line` into a byte offset. Columns that fall within a character
+    /// round up to the end of it, and columns past the end of the line clamp to its length.
+    pub fn byte_offset(self, line: &str, column: u32) -> usize {
+        let mut units = 0;
+        for (offset, ch) in line.char_indices() {
+            if units >= column {
+                return offset;
+            }
+            units += self.char_len(ch);
+        }
+        line.len()
+    }
+
+    /// Converts a `column` of `line` counted in this encoding into a column counted in `other`.
+    pub fn convert_column(self, line: &str, column: u32, other: Self) -> u32 {
+        other.text_len(&line[..self.byte_offset(line, column)])
+    }
+}
+
+impl From<PicklsColumnUnit> for PositionEncoding {
+    fn from(column_unit: PicklsColumnUnit) -> Self {
+        match column_unit {
+            PicklsColumnUnit::Byte => Self::Utf8,
+            PicklsColumnUnit::Char => Self::Utf32,
+            PicklsColumnUnit::Utf16 => Self::Utf16,
+        }
+    }
+}
+
+#[test]
+fn test_position_encoding() {
+    let capabilities: ClientCapabilities = serde_json::from_value(json!({
+        "general": {"positionEncodings": ["utf-7", "utf-32", "utf-8"]}
+    }))
+    .unwrap();
+    assert_eq!(
+        PositionEncoding::negotiate(&capabilities),
+        PositionEncoding::Utf32
+    );
+    assert_eq!(
+        PositionEncoding::negotiate(&ClientCapabilities::default()),
+        PositionEncoding::Utf16
+    );
+    // "é" is 2 bytes and 1 UTF-16 code unit, and "🌍" is 4 bytes and 2 UTF-16 code units.
+    let line = "é🌍x";
+    let column = |from: PositionEncoding, column, to| from.convert_column(line, column, to);
+    assert_eq!(
+        column(PositionEncoding::Utf8, 6, PositionEncoding::Utf16),
+        3
+    );
+    assert_eq!(
+        column(PositionEncoding::Utf32, 2, PositionEncoding::Utf8),
+        6
+    );
+    assert_eq!(
+        column(PositionEncoding::Utf16, 3, PositionEncoding::Utf32),
+        2
+    );
+    // Columns in the middle of a character round up, and columns past the end clamp.
+    assert_eq!(
+        column(PositionEncoding::Utf8, 3, PositionEncoding::Utf32),
+        2
+    );
+    assert_eq!(
+        column(PositionEncoding::Utf32, 9, PositionEncoding::Utf16),
+        4
+    );
+}
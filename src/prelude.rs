@@ -1,6 +1,6 @@
 pub use std::{
     cell::RefCell,
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeSet, HashMap, HashSet},
     fs::read_to_string,
     path::PathBuf,
     process::Command,
@@ -9,6 +9,7 @@ pub use std::{
 };
 
 pub use anyhow::{Context, Result};
+pub use crossbeam_channel::{Receiver, Sender};
 pub use futures::future::join_all;
 pub use lsp_types::{notification::*, *};
 pub use nix::unistd::Pid;
@@ -19,7 +20,10 @@ pub use tokio::runtime::Runtime;
 
 pub(crate) use crate::{
     ai::*,
+    background::*,
+    cancellation::*,
     client::*,
+    code_actions::*,
     config::*,
     diagnostic::*,
     diagnostic_severity::*,
@@ -28,11 +32,16 @@ pub(crate) use crate::{
     document_storage::*,
     document_version::*,
     errno::*,
+    errorformat::*,
     job::*,
     language_server::*,
+    position_encoding::*,
     progress::*,
     server::*,
+    structured_output::*,
     tags::*,
+    text_edits::*,
+    tmpfile::*,
     tool::*,
     utils::*,
     workspace::*,
@@ -2,37 +2,64 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::prelude::*;
 
+/// Reports the progress of a request under a token of its own, which clients may offer to cancel.
+/// Without a token (when the client doesn't support work done progress) nothing is reported.
 pub struct ProgressNotifier {
     pub counter: AtomicUsize,
-    pub uri: Uri,
-    pub version: DocumentVersion,
+    pub token: Option<ProgressToken>,
     pub total: usize,
     pub client: Client,
 }
 
 impl ProgressNotifier {
-    pub fn new(client: Client, uri: Uri, version: DocumentVersion, total: usize) -> Self {
-        Self {
+    /// Starts reporting progress, out of `total` steps.
+    pub fn begin(
+        client: Client,
+        token: Option<ProgressToken>,
+        title: &str,
+        total: usize,
+    ) -> Result<Self> {
+        let notifier = Self {
             counter: AtomicUsize::new(0),
-            uri,
-            version,
+            token,
             total,
             client,
-        }
+        };
+        notifier.send(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+            title: title.to_string(),
+            cancellable: Some(true),
+            message: None,
+            percentage: Some(0),
+        }))?;
+        Ok(notifier)
     }
+
+    /// Reports that one more step is done.
     pub fn notify(&self) {
-        let counter = self.counter.fetch_add(1, Ordering::SeqCst);
-        let progress = make_progress_params(
-            "running inline-assist",
-            self.uri.clone(),
-            self.version,
-            counter + 1,
-            // The extra 1 is for the final completion message.
-            self.total,
-        );
-        let r = self.client.send_notification::<Progress, _>(progress);
+        let counter = self.counter.fetch_add(1, Ordering::SeqCst) + 1;
+        let percentage = (counter * 100).checked_div(self.total).unwrap_or(100) as u32;
+        let r = self.send(WorkDoneProgress::Report(WorkDoneProgressReport {
+            cancellable: Some(true),
+            message: Some(format!("{counter}/{total}", total = self.total)),
+            percentage: Some(percentage),
+        }));
         if let Err(e) = r {
             log::error!("error sending progress notification: {e:?}");
         }
     }
+
+    pub fn end(&self) -> Result<()> {
+        self.send(WorkDoneProgress::End(WorkDoneProgressEnd { message: None }))
+    }
+
+    fn send(&self, progress: WorkDoneProgress) -> Result<()> {
+        let Some(token) = self.token.clone() else {
+            return Ok(());
+        };
+        self.client
+            .send_notification::<Progress, _>(ProgressParams {
+                token,
+                value: ProgressParamsValue::WorkDone(progress),
+            })
+    }
 }
@@ -1,85 +1,270 @@
+use std::ops::ControlFlow;
+
 use lsp_types::request::*;
 
 use crate::prelude::*;
 
 pub fn run_server<F, T>(f: F) -> Result<()>
 where
-    F: FnOnce(Client) -> T,
+    F: FnOnce(Client, Sender<BackgroundMessage>) -> T,
     T: LanguageServer,
 {
-    let stdin = std::io::stdin();
     let stdout = Rc::new(RefCell::new(std::io::stdout().lock()));
     let client = Client::new(stdout);
-    // let mut read_buf = [0i8; 4096];
-    let mut backend = f(client.clone());
+    let (background_sender, background_receiver) = crossbeam_channel::unbounded();
+    let mut backend = f(client.clone(), background_sender);
+    let in_flight = InFlightRequests::default();
+    let rpc_receiver = spawn_rpc_reader(in_flight.clone());
     log::info!("Server is running");
-    for rpc in parse_json_rpc(stdin.lock()) {
-        let rpc = rpc.context("Error parsing JSON")?;
-        // log::info!("Received message: {:#?}", rpc);
-        // This is a Request.
-        let id = rpc.id.clone();
-        match rpc.method.as_str() {
-            Initialize::METHOD => {
-                client.write_response(id, backend.initialize(rpc.take_params()?))?;
-            }
-            Initialized::METHOD => {
-                backend
-                    .initialized(rpc.take_params()?)
-                    .context("error in initialized")?;
-            }
-            SetTrace::METHOD => {
-                backend.set_trace(rpc.take_params()?);
-            }
-            WorkspaceSymbolRequest::METHOD => {
-                client.write_response(id, backend.workspace_symbol(rpc.take_params()?))?;
-            }
-            ExecuteCommand::METHOD => {
-                client.write_response(id, backend.execute_command(rpc.take_params()?))?;
-            }
-            CodeActionRequest::METHOD => {
-                client.write_response(id, backend.code_action(rpc.take_params()?))?;
-            }
-            DidChangeTextDocument::METHOD => {
-                backend
-                    .did_change(rpc.take_params()?)
-                    .context("error in did_change")?;
-            }
-            DidChangeConfiguration::METHOD => {
-                backend
-                    .did_change_configuration(rpc.take_params()?)
-                    .context("error in did_change_configuration")?;
-            }
-            DidOpenTextDocument::METHOD => {
-                backend
-                    .did_open(rpc.take_params()?)
-                    .context("error in did_open")?;
+    loop {
+        crossbeam_channel::select! {
+            recv(rpc_receiver) -> rpc => {
+                // The reader hangs up when stdin is closed.
+                let Ok(rpc) = rpc else {
+                    break;
+                };
+                let rpc = rpc.context("Error parsing JSON")?;
+                if dispatch_rpc(&client, &mut backend, &in_flight, rpc)?.is_break() {
+                    break;
+                }
             }
-            DidCloseTextDocument::METHOD => {
+            recv(background_receiver) -> message => {
+                // The backend holds a sender for its whole lifetime, so this cannot disconnect.
                 backend
-                    .did_close(rpc.take_params()?)
-                    .context("error in did_close")?;
+                    .on_background_message(message?)
+                    .context("error in on_background_message")?;
             }
-            WillSaveTextDocument::METHOD => {
-                backend
-                    .will_save(rpc.take_params()?)
-                    .context("error in will_save")?;
-            }
-            Formatting::METHOD => {
-                client.write_response(id, backend.formatting(rpc.take_params()?))?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads JSON-RPC messages from stdin on a dedicated thread so that the main loop can also wait on
+/// messages from background work. Cancellations are handled right here, since the main loop may be
+/// busy with the very request that is being cancelled.
+fn spawn_rpc_reader(in_flight: InFlightRequests) -> Receiver<Result<JsonRpc>> {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    std::thread::spawn(move || {
+        for rpc in parse_json_rpc(std::io::stdin().lock()) {
+            if let Ok(rpc) = &rpc {
+                match rpc.method.as_str() {
+                    Cancel::METHOD => {
+                        match serde_json::from_value::<CancelParams>(
+                            rpc.params.clone().unwrap_or_default(),
+                        ) {
+                            Ok(CancelParams { id }) => in_flight.cancel(&match id {
+                                NumberOrString::Number(id) => MessageId::Number(id.into()),
+                                NumberOrString::String(id) => MessageId::String(id),
+                            }),
+                            Err(error) => log::warn!("invalid $/cancelRequest: {error}"),
+                        }
+                        continue;
+                    }
+                    WorkDoneProgressCancel::METHOD => {
+                        match serde_json::from_value::<WorkDoneProgressCancelParams>(
+                            rpc.params.clone().unwrap_or_default(),
+                        ) {
+                            Ok(params) => in_flight.cancel_progress(&params.token),
+                            Err(error) => {
+                                log::warn!("invalid window/workDoneProgress/cancel: {error}")
+                            }
+                        }
+                        continue;
+                    }
+                    "" => {}
+                    _ => {
+                        if let Some(id) = &rpc.id {
+                            in_flight.register(id.clone());
+                        }
+                    }
+                }
             }
-            Shutdown::METHOD => {
-                client.write_response(id, backend.shutdown())?;
-                log::info!("Shutting down");
+            if sender.send(rpc).is_err() {
                 break;
             }
-            Cancel::METHOD => { /* ignore */ }
-            DocumentDiagnosticRequest::METHOD => { /*ignore*/ }
-            WorkspaceDiagnosticRequest::METHOD => { /*ignore*/ }
-            DidSaveTextDocument::METHOD => { /*ignore*/ }
-            _ => {
-                log::warn!("Unhandled method: {}", rpc.method);
-            }
         }
+        log::info!("stdin closed");
+    });
+    receiver
+}
+
+/// Answers a request, unless it was cancelled while it was being handled.
+fn respond<T: Serialize>(
+    client: &Client,
+    id: Option<MessageId>,
+    cancellation: &CancellationToken,
+    result: Result<T>,
+) -> Result<()> {
+    match cancellation.check() {
+        Ok(()) => client.write_response(id, result),
+        Err(error) => client.write_response::<()>(id, Err(error)),
     }
-    Ok(())
+}
+
+fn dispatch_rpc<T: LanguageServer>(
+    client: &Client,
+    backend: &mut T,
+    in_flight: &InFlightRequests,
+    rpc: JsonRpc,
+) -> Result<ControlFlow<()>> {
+    // Responses to our own requests share ids with the client's requests, so only requests are
+    // tracked.
+    let Some(id) = rpc.id.clone().filter(|_| !rpc.method.is_empty()) else {
+        return dispatch_message(client, backend, &CancellationToken::default(), rpc);
+    };
+    let cancellation = in_flight.token(&id);
+    let result = if cancellation.is_cancelled() {
+        // The client gave up on the request before we got to it.
+        log::info!(
+            "skipping cancelled request [method={method}, id={id:?}]",
+            method = rpc.method
+        );
+        client
+            .write_response::<()>(Some(id.clone()), Err(RequestCancelled.into()))
+            .map(|()| ControlFlow::Continue(()))
+    } else {
+        dispatch_message(client, backend, &cancellation, rpc)
+    };
+    in_flight.finish(&id);
+    result
+}
+
+fn dispatch_message<T: LanguageServer>(
+    client: &Client,
+    backend: &mut T,
+    cancellation: &CancellationToken,
+    rpc: JsonRpc,
+) -> Result<ControlFlow<()>> {
+    // log::info!("Received message: {:#?}", rpc);
+    if rpc.method.is_empty() {
+        // This is a response to a request we sent to the client (ie: workspace/applyEdit).
+        match rpc.error {
+            Some(error) => log::warn!("client returned an error [id={:?}, error={error}]", rpc.id),
+            None => log::trace!("client responded [id={:?}]", rpc.id),
+        }
+        return Ok(ControlFlow::Continue(()));
+    }
+    // This is a Request.
+    let id = rpc.id.clone();
+    match rpc.method.as_str() {
+        Initialize::METHOD => {
+            respond(
+                client,
+                id,
+                cancellation,
+                backend.initialize(rpc.take_params()?),
+            )?;
+        }
+        Initialized::METHOD => {
+            backend
+                .initialized(rpc.take_params()?)
+                .context("error in initialized")?;
+        }
+        SetTrace::METHOD => {
+            backend.set_trace(rpc.take_params()?);
+        }
+        WorkspaceSymbolRequest::METHOD => {
+            respond(
+                client,
+                id,
+                cancellation,
+                backend.workspace_symbol(rpc.take_params()?, cancellation),
+            )?;
+        }
+        ExecuteCommand::METHOD => {
+            respond(
+                client,
+                id,
+                cancellation,
+                backend.execute_command(rpc.take_params()?, cancellation),
+            )?;
+        }
+        CodeActionRequest::METHOD => {
+            respond(
+                client,
+                id,
+                cancellation,
+                backend.code_action(rpc.take_params()?, cancellation),
+            )?;
+        }
+        DidChangeTextDocument::METHOD => {
+            backend
+                .did_change(rpc.take_params()?)
+                .context("error in did_change")?;
+        }
+        DidChangeConfiguration::METHOD => {
+            backend
+                .did_change_configuration(rpc.take_params()?)
+                .context("error in did_change_configuration")?;
+        }
+        DidOpenTextDocument::METHOD => {
+            backend
+                .did_open(rpc.take_params()?)
+                .context("error in did_open")?;
+        }
+        DidCloseTextDocument::METHOD => {
+            backend
+                .did_close(rpc.take_params()?)
+                .context("error in did_close")?;
+        }
+        WillSaveTextDocument::METHOD => {
+            backend
+                .will_save(rpc.take_params()?)
+                .context("error in will_save")?;
+        }
+        WillSaveWaitUntil::METHOD => {
+            respond(
+                client,
+                id,
+                cancellation,
+                backend.will_save_wait_until(rpc.take_params()?, cancellation),
+            )?;
+        }
+        DidSaveTextDocument::METHOD => {
+            backend
+                .did_save(rpc.take_params()?)
+                .context("error in did_save")?;
+        }
+        Formatting::METHOD => {
+            respond(
+                client,
+                id,
+                cancellation,
+                backend.formatting(rpc.take_params()?, cancellation),
+            )?;
+        }
+        RangeFormatting::METHOD => {
+            respond(
+                client,
+                id,
+                cancellation,
+                backend.range_formatting(rpc.take_params()?, cancellation),
+            )?;
+        }
+        Shutdown::METHOD => {
+            respond(client, id, cancellation, backend.shutdown())?;
+            log::info!("Shutting down");
+            return Ok(ControlFlow::Break(()));
+        }
+        DocumentDiagnosticRequest::METHOD => {
+            respond(
+                client,
+                id,
+                cancellation,
+                backend.document_diagnostic(rpc.take_params()?),
+            )?;
+        }
+        WorkspaceDiagnosticRequest::METHOD => {
+            respond(
+                client,
+                id,
+                cancellation,
+                backend.workspace_diagnostic(rpc.take_params()?),
+            )?;
+        }
+        _ => {
+            log::warn!("Unhandled method: {}", rpc.method);
+        }
+    }
+    Ok(ControlFlow::Continue(()))
 }
@@ -0,0 +1,524 @@
+use quick_xml::{
+    XmlVersion,
+    events::{BytesStart, Event},
+};
+
+use crate::prelude::*;
+
+/// Parses the complete output of a linter whose `output_format` is not `regex`. Diagnostics that
+/// don't name a file are attributed to `default_filename`.
+pub fn parse_structured_output(
+    output_format: PicklsOutputFormat,
+    linter_config: &PicklsLinterConfig,
+    output: &str,
+    default_filename: &str,
+) -> Result<Vec<PicklsDiagnostic>> {
+    let linter = linter_config.program.as_str();
+    if output.trim().is_empty() {
+        // Plenty of tools print nothing at all when there is nothing to report.
+        return Ok(Vec::new());
+    }
+    match output_format {
+        PicklsOutputFormat::Regex => {
+            anyhow::bail!("regex output is not structured [linter={linter}]")
+        }
+        PicklsOutputFormat::Json => {
+            let json_config = linter_config.json.as_ref().with_context(|| {
+                format!("linter `{linter}` has output_format json but no json configuration")
+            })?;
+            parse_json_output(linter, json_config, output, default_filename)
+        }
+        PicklsOutputFormat::Sarif => parse_sarif_output(linter, output, default_filename),
+        PicklsOutputFormat::Checkstyle => parse_checkstyle_output(linter, output, default_filename),
+    }
+}
+
+/// Reads `output` as a single JSON document, falling back to one JSON document per line.
+fn parse_json_documents(output: &str) -> Result<Vec<Value>> {
+    match serde_json::from_str(output) {
+        Ok(value) => Ok(vec![value]),
+        Err(error) => output
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<std::result::Result<Vec<Value>, _>>()
+            .map_err(|_| anyhow::Error::new(error).context("invalid JSON output")),
+    }
+}
+
+/// A diagnostic object found in JSON output along with the objects that enclose it, innermost
+/// last.
+struct JsonDiagnostic<'a> {
+    parents: Vec<&'a Value>,
+    value: &'a Value,
+}
+
+fn collect_json_diagnostics<'a>(root: &'a Value, path: &str) -> Vec<JsonDiagnostic<'a>> {
+    let mut found = vec![JsonDiagnostic {
+        parents: Vec::new(),
+        value: root,
+    }];
+    for segment in path.split('.').filter(|segment| !segment.is_empty()) {
+        found = found
+            .into_iter()
+            .flat_map(|JsonDiagnostic { parents, value }| {
+                let children: Vec<JsonDiagnostic<'a>> = match (segment, value) {
+                    ("[]", Value::Array(items)) => items
+                        .iter()
+                        .map(|item| JsonDiagnostic {
+                            parents: parents.clone(),
+                            value: item,
+                        })
+                        .collect(),
+                    (_, Value::Object(map)) => map
+                        .get(segment)
+                        .map(|child| {
+                            let mut parents = parents.clone();
+                            parents.push(value);
+                            JsonDiagnostic {
+                                parents,
+                                value: child,
+                            }
+                        })
+                        .into_iter()
+                        .collect(),
+                    (_, Value::Array(items)) => segment
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|index| items.get(index))
+                        .map(|child| JsonDiagnostic {
+                            parents: parents.clone(),
+                            value: child,
+                        })
+                        .into_iter()
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                children
+            })
+            .collect();
+    }
+    // Whatever the path led to is either a list of diagnostics or a single diagnostic.
+    found
+        .into_iter()
+        .flat_map(|JsonDiagnostic { parents, value }| match value {
+            Value::Array(items) => items
+                .iter()
+                .map(|item| JsonDiagnostic {
+                    parents: parents.clone(),
+                    value: item,
+                })
+                .collect::<Vec<_>>(),
+            _ => vec![JsonDiagnostic { parents, value }],
+        })
+        .collect()
+}
+
+/// Looks up a dot-separated `path` on a JSON value. Arrays are indexed by number.
+pub fn lookup_json_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .filter(|segment| !segment.is_empty())
+        .try_fold(value, |value, segment| match value {
+            Value::Object(map) => map.get(segment),
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+            _ => None,
+        })
+}
+
+impl JsonDiagnostic<'_> {
+    fn lookup(&self, path: &str) -> Option<&Value> {
+        let mut path = path;
+        let mut value = self.value;
+        let mut parents = self.parents.iter().rev();
+        while let Some(rest) = path.strip_prefix('^') {
+            value = parents.next()?;
+            path = rest.strip_prefix('.').unwrap_or(rest);
+        }
+        lookup_json_path(value, path)
+    }
+
+    fn lookup_string(&self, path: Option<&String>) -> Option<String> {
+        json_value_to_string(self.lookup(path?)?)
+    }
+
+    fn lookup_u32(&self, path: Option<&String>) -> Option<u32> {
+        json_value_to_u32(self.lookup(path?)?)
+    }
+
+    fn fix(&self, fix_config: &PicklsJsonFixConfig) -> Option<PicklsFix> {
+        let edit_values: Vec<&Value> = match &fix_config.edits {
+            Some(path) => match self.lookup(path)? {
+                Value::Array(items) => items.iter().collect(),
+                value => vec![value],
+            },
+            None => vec![self.value],
+        };
+        let lookup_u32 = |value: &Value, path: Option<&String>| {
+            json_value_to_u32(lookup_json_path(value, path?)?)
+        };
+        let edits = edit_values
+            .into_iter()
+            .map(|value| {
+                let new_text = json_value_to_string(lookup_json_path(value, &fix_config.text)?)?;
+                let offsets = fix_config
+                    .range
+                    .as_ref()
+                    .and_then(|path| lookup_json_path(value, path)?.as_array())
+                    .and_then(|range| {
+                        Some((
+                            json_value_to_u32(range.first()?)?,
+                            json_value_to_u32(range.get(1)?)?,
+                        ))
+                    });
+                let range = match (offsets, lookup_u32(value, fix_config.line.as_ref())) {
+                    (Some((start, end)), _) => PicklsFixRange::Offsets { start, end },
+                    (None, Some(line)) => PicklsFixRange::Positions {
+                        line,
+                        column: lookup_u32(value, fix_config.column.as_ref()),
+                        end_line: lookup_u32(value, fix_config.end_line.as_ref()),
+                        end_column: lookup_u32(value, fix_config.end_column.as_ref()),
+                    },
+                    (None, None) => PicklsFixRange::Diagnostic,
+                };
+                Some(PicklsFixEdit { range, new_text })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if edits.is_empty() {
+            return None;
+        }
+        Some(PicklsFix {
+            title: self.lookup_string(fix_config.title.as_ref()),
+            edits,
+        })
+    }
+}
+
+pub fn json_value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+pub fn json_value_to_u32(value: &Value) -> Option<u32> {
+    match value {
+        Value::Number(n) => n.as_u64().and_then(|n| u32::try_from(n).ok()),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn parse_json_output(
+    linter: &str,
+    json_config: &PicklsJsonOutputConfig,
+    output: &str,
+    default_filename: &str,
+) -> Result<Vec<PicklsDiagnostic>> {
+    let documents = parse_json_documents(output)?;
+    let mut diagnostics = Vec::new();
+    for document in documents.iter() {
+        for json_diagnostic in collect_json_diagnostics(document, &json_config.diagnostics) {
+            let Some(line) = json_diagnostic.lookup_u32(Some(&json_config.line)) else {
+                log::debug!(
+                    "skipping JSON diagnostic without a line [linter={linter}, path={path}]",
+                    path = json_config.line
+                );
+                continue;
+            };
+            diagnostics.push(PicklsDiagnostic {
+                linter: linter.to_string(),
+                filename: json_diagnostic
+                    .lookup_string(json_config.filename.as_ref())
+                    .unwrap_or_else(|| default_filename.to_string()),
+                line,
+                start_column: json_diagnostic.lookup_u32(json_config.column.as_ref()),
+                end_line: json_diagnostic.lookup_u32(json_config.end_line.as_ref()),
+                end_column: json_diagnostic
+                    .lookup_u32(json_config.end_column.as_ref())
+                    .map(|column| {
+                        if json_config.end_column_inclusive {
+                            column
+                        } else {
+                            // Convert from exclusive to inclusive.
+                            column.saturating_sub(1)
+                        }
+                    }),
+                severity: json_diagnostic
+                    .lookup_string(json_config.severity.as_ref())
+                    .map(|severity| PicklsDiagnosticSeverity { severity }),
+                code: json_diagnostic.lookup_string(json_config.code.as_ref()),
+                description: json_diagnostic.lookup_string(Some(&json_config.message)),
+                related: Vec::new(),
+                fix: json_config
+                    .fix
+                    .as_ref()
+                    .and_then(|fix_config| json_diagnostic.fix(fix_config)),
+            });
+        }
+    }
+    Ok(diagnostics)
+}
+
+#[test]
+fn test_parse_json_output() {
+    let eslint: PicklsJsonOutputConfig = serde_json::from_value(json!({
+        "diagnostics": "[].messages",
+        "filename": "^.filePath",
+        "line": "line",
+        "column": "column",
+        "end_line": "endLine",
+        "end_column": "endColumn",
+        "severity": "severity",
+        "code": "ruleId",
+        "message": "message",
+    }))
+    .unwrap();
+    let output = r#"[{"filePath": "/src/a.js", "messages": [
+        {"ruleId": "semi", "severity": 2, "message": "Missing semicolon.",
+         "line": 3, "column": 10, "endLine": 4, "endColumn": 2}]}]"#;
+    let diagnostics = parse_json_output("eslint", &eslint, output, "/src/b.js").unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    let diagnostic = &diagnostics[0];
+    assert_eq!(diagnostic.filename, "/src/a.js");
+    assert_eq!((diagnostic.line, diagnostic.start_column), (3, Some(10)));
+    assert_eq!(
+        (diagnostic.end_line, diagnostic.end_column),
+        (Some(4), Some(1))
+    );
+    assert_eq!(diagnostic.code.as_deref(), Some("semi"));
+    assert_eq!(
+        diagnostic.description.as_deref(),
+        Some("Missing semicolon.")
+    );
+
+    // One document per line, and no filename path.
+    let hadolint: PicklsJsonOutputConfig = serde_json::from_value(json!({
+        "line": "line",
+        "message": "message",
+    }))
+    .unwrap();
+    let output = "{\"line\": 1, \"message\": \"a\"}\n{\"line\": \"2\", \"message\": \"b\"}\n";
+    let diagnostics = parse_json_output("hadolint", &hadolint, output, "/Dockerfile").unwrap();
+    assert_eq!(
+        diagnostics
+            .iter()
+            .map(|d| (d.filename.as_str(), d.line))
+            .collect::<Vec<_>>(),
+        vec![("/Dockerfile", 1), ("/Dockerfile", 2)]
+    );
+
+    let inclusive: PicklsJsonOutputConfig = serde_json::from_value(json!({
+        "line": "line",
+        "column": "column",
+        "end_column": "end",
+        "end_column_inclusive": true,
+        "message": "message",
+    }))
+    .unwrap();
+    let output = r#"{"line": 2, "column": 3, "end": 5, "message": "a"}"#;
+    let diagnostics = parse_json_output("tool", &inclusive, output, "/a").unwrap();
+    assert_eq!(
+        (diagnostics[0].start_column, diagnostics[0].end_column),
+        (Some(3), Some(5))
+    );
+}
+
+fn parse_sarif_output(
+    linter: &str,
+    output: &str,
+    default_filename: &str,
+) -> Result<Vec<PicklsDiagnostic>> {
+    let sarif: Value = serde_json::from_str(output).context("invalid SARIF output")?;
+    let mut diagnostics = Vec::new();
+    let runs = sarif.get("runs").and_then(Value::as_array);
+    for result in runs
+        .into_iter()
+        .flatten()
+        .filter_map(|run| run.get("results").and_then(Value::as_array))
+        .flatten()
+    {
+        let Some(location) = lookup_json_path(result, "locations.0.physicalLocation") else {
+            log::debug!("skipping SARIF result without a physical location [linter={linter}]");
+            continue;
+        };
+        let region = location.get("region");
+        let region_u32 = |key: &str| region?.get(key).and_then(json_value_to_u32);
+        let level = result
+            .get("level")
+            .and_then(Value::as_str)
+            // Per the SARIF spec, a result without a level is a warning.
+            .unwrap_or("warning");
+        diagnostics.push(PicklsDiagnostic {
+            linter: linter.to_string(),
+            filename: lookup_json_path(location, "artifactLocation.uri")
+                .and_then(Value::as_str)
+                .map(file_uri_to_path)
+                .unwrap_or_else(|| default_filename.to_string()),
+            line: region_u32("startLine").unwrap_or(1),
+            start_column: region_u32("startColumn"),
+            end_line: region_u32("endLine"),
+            // Convert from exclusive to inclusive.
+            end_column: region_u32("endColumn").map(|column| column.saturating_sub(1)),
+            severity: Some(PicklsDiagnosticSeverity {
+                severity: match level {
+                    "none" => "hint",
+                    level => level,
+                }
+                .to_string(),
+            }),
+            code: result
+                .get("ruleId")
+                .or_else(|| lookup_json_path(result, "rule.id"))
+                .and_then(json_value_to_string),
+            description: lookup_json_path(result, "message.text")
+                .or_else(|| lookup_json_path(result, "message.markdown"))
+                .and_then(json_value_to_string),
+            related: Vec::new(),
+            fix: None,
+        });
+    }
+    Ok(diagnostics)
+}
+
+/// Turns a "file://" URI (or a plain, possibly relative, path) into a path.
+fn file_uri_to_path(uri: &str) -> String {
+    let Some(path) = uri.strip_prefix("file://") else {
+        return percent_decode(uri);
+    };
+    // Skip an authority, if present (ie: file://localhost/path).
+    let path = path.find('/').map_or(path, |slash| &path[slash..]);
+    percent_decode(path)
+}
+
+#[test]
+fn test_parse_sarif_output() {
+    let output = r#"{"version": "2.1.0", "runs": [{"results": [
+        {"ruleId": "SC2086", "level": "note", "message": {"text": "Double quote"},
+         "locations": [{"physicalLocation": {
+            "artifactLocation": {"uri": "file:///src/my%20script.sh"},
+            "region": {"startLine": 2, "startColumn": 6, "endLine": 2, "endColumn": 10}}}]},
+        {"message": {"text": "No level"},
+         "locations": [{"physicalLocation": {"artifactLocation": {"uri": "lib.sh"}}}]}
+    ]}]}"#;
+    let diagnostics = parse_sarif_output("shellcheck", output, "/src/x.sh").unwrap();
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].filename, "/src/my script.sh");
+    assert_eq!(
+        (diagnostics[0].line, diagnostics[0].start_column),
+        (2, Some(6))
+    );
+    assert_eq!(diagnostics[0].end_column, Some(9));
+    assert_eq!(diagnostics[0].code.as_deref(), Some("SC2086"));
+    assert_eq!(diagnostics[0].severity.as_ref().unwrap().severity, "note");
+    assert_eq!(diagnostics[1].filename, "lib.sh");
+    assert_eq!(diagnostics[1].line, 1);
+    assert_eq!(
+        diagnostics[1].severity.as_ref().unwrap().severity,
+        "warning"
+    );
+}
+
+fn parse_checkstyle_output(
+    linter: &str,
+    output: &str,
+    default_filename: &str,
+) -> Result<Vec<PicklsDiagnostic>> {
+    let mut reader = quick_xml::Reader::from_str(output);
+    let mut diagnostics = Vec::new();
+    let mut filename: Option<String> = None;
+    loop {
+        let event = reader.read_event().with_context(|| {
+            format!(
+                "invalid checkstyle output at byte {position} [linter={linter}]",
+                position = reader.error_position()
+            )
+        })?;
+        match event {
+            Event::Eof => break,
+            Event::Start(element) if element.local_name().as_ref() == "file" => {
+                filename = checkstyle_attribute(&element, "name")?;
+            }
+            Event::End(element) if element.local_name().as_ref() == "file" => filename = None,
+            Event::Start(element) | Event::Empty(element)
+                if element.local_name().as_ref() == "error" =>
+            {
+                let Some(line) = checkstyle_attribute(&element, "line")?
+                    .and_then(|line| line.trim().parse().ok())
+                else {
+                    log::debug!("skipping checkstyle error without a line [linter={linter}]");
+                    continue;
+                };
+                diagnostics.push(PicklsDiagnostic {
+                    linter: linter.to_string(),
+                    filename: filename
+                        .clone()
+                        .unwrap_or_else(|| default_filename.to_string()),
+                    line,
+                    start_column: checkstyle_attribute(&element, "column")?
+                        .and_then(|column| column.trim().parse().ok()),
+                    end_line: None,
+                    end_column: None,
+                    severity: checkstyle_attribute(&element, "severity")?
+                        .map(|severity| PicklsDiagnosticSeverity { severity }),
+                    code: checkstyle_attribute(&element, "source")?,
+                    description: checkstyle_attribute(&element, "message")?,
+                    related: Vec::new(),
+                    fix: None,
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(diagnostics)
+}
+
+/// Looks up an attribute of a checkstyle element, with its entities and character references
+/// resolved.
+fn checkstyle_attribute(element: &BytesStart, name: &str) -> Result<Option<String>> {
+    let Some(attribute) = element.try_get_attribute(name)? else {
+        return Ok(None);
+    };
+    Ok(Some(
+        attribute
+            .normalized_value(XmlVersion::Implicit1_0)?
+            .into_owned(),
+    ))
+}
+
+#[test]
+fn test_parse_checkstyle_output() {
+    let output = r#"<?xml version="1.0" encoding="utf-8"?>
+<checkstyle version="4.3">
+  <file name="/src/a.js">
+    <error line="1" column="5" severity="warning" message="Don&apos;t use &quot;var&quot; &amp; friends" source="eslint.rules.no-var" />
+    <error line='7' severity='error' message='Missing &#x3B;'/>
+    <!-- <error line="9" message="commented out"/> -->
+    <error line="8" message="Expected a > b"></error>
+  </file>
+  <file name="/src/b.js"></file>
+</checkstyle>"#;
+    let diagnostics = parse_checkstyle_output("eslint", output, "/src/x.js").unwrap();
+    assert_eq!(diagnostics.len(), 3);
+    assert_eq!(diagnostics[0].filename, "/src/a.js");
+    assert_eq!(
+        (diagnostics[0].line, diagnostics[0].start_column),
+        (1, Some(5))
+    );
+    assert_eq!(
+        diagnostics[0].description.as_deref(),
+        Some("Don't use \"var\" & friends")
+    );
+    assert_eq!(diagnostics[0].code.as_deref(), Some("eslint.rules.no-var"));
+    assert_eq!(diagnostics[1].line, 7);
+    assert_eq!(diagnostics[1].description.as_deref(), Some("Missing ;"));
+    assert_eq!(diagnostics[1].severity.as_ref().unwrap().severity, "error");
+    assert_eq!(diagnostics[2].line, 8);
+    assert_eq!(
+        diagnostics[2].description.as_deref(),
+        Some("Expected a > b")
+    );
+
+    assert!(parse_checkstyle_output("eslint", "<checkstyle></file>", "/src/x.js").is_err());
+}
@@ -1,5 +1,6 @@
 use std::{
     io::{BufRead, BufReader},
+    os::unix::process::CommandExt as _,
     str::FromStr,
 };
 
@@ -19,7 +20,8 @@ fn parse_multi_regex_query(query: &str) -> Result<Vec<Regex>> {
 }
 fn construct_ctags_command(folders: &Vec<PathBuf>, excludes: &Vec<String>) -> Result<Command> {
     let mut cmd = Command::new("ctags");
-    cmd.stdout(std::process::Stdio::piped())
+    cmd.process_group(0)
+        .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::null());
     cmd.arg("--options=NONE")
         .arg("--fields=+K")
@@ -48,20 +50,36 @@ pub(crate) fn find_symbols(
     folders: &Vec<PathBuf>,
     excludes: &Vec<String>,
     ctags_timeout: Duration,
+    cancellation: &CancellationToken,
 ) -> Result<Vec<SymbolInformation>> {
     let ctags_timeout_after = Instant::now() + ctags_timeout;
-    parse_ctags_output(
-        parse_multi_regex_query(query)?,
-        construct_ctags_command(folders, excludes)?.spawn()?,
-        ctags_timeout_after,
-    )
+    let regexes = parse_multi_regex_query(query)?;
+    let mut proc = construct_ctags_command(folders, excludes)?.spawn()?;
+    let pid = Pid::from_raw(proc.id() as i32);
+    // Cancelling the request kills ctags, which ends its output early.
+    let tracked = cancellation.track_process(pid);
+    let symbols = parse_ctags_output(regexes, &mut proc, ctags_timeout_after);
+    if symbols.is_err() {
+        // Make sure ctags doesn't outlive a failure to read its output. It hasn't been reaped yet,
+        // so its process group can't have been reused.
+        kill_process_group(pid);
+    }
+    if let Err(error) = reap(&mut proc, || drop(tracked)) {
+        log::warn!("failed to reap ctags [pid={pid}, error={error}]");
+    }
+    let symbols = symbols?;
+    cancellation.check()?;
+    Ok(symbols)
 }
 
+/// Reads the symbols matching `regexes` from the output of ctags, until it ends or runs out of
+/// time. ctags is killed when it is stopped early, but it is left for the caller to reap.
 pub(crate) fn parse_ctags_output(
     regexes: Vec<Regex>,
-    mut proc: std::process::Child,
+    proc: &mut std::process::Child,
     ctags_timeout_after: Instant,
 ) -> Result<Vec<SymbolInformation>> {
+    let pid = Pid::from_raw(proc.id() as i32);
     let mut symbols = Vec::new();
     let stdout = BufReader::new(
         proc.stdout
@@ -74,9 +92,11 @@ pub(crate) fn parse_ctags_output(
         // log::info!("parsing ctags line: {line}");
         if Instant::now() > ctags_timeout_after {
             log::warn!("ctags timed out");
+            kill_process_group(pid);
             break;
         }
         if symbols.len() >= MAX_CTAGS_SYMBOLS {
+            kill_process_group(pid);
             break;
         }
         // log::info!("parsing ctags line: {line}");
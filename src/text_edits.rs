@@ -0,0 +1,406 @@
+use similar::{DiffOp, TextDiff};
+
+use crate::prelude::*;
+
+/// Hunks larger than this (in chars, on either side) are replaced wholesale rather than diffed
+/// character by character, which would be quadratic in the worst case.
+const MAX_CHAR_DIFF_LEN: usize = 10_000;
+
+/// Computes the edits that turn `original` into `formatted`. The texts are diffed line by line
+/// first, and then character by character within each changed hunk, so that edits touch as
+/// little of the document as possible. Positions are counted in `encoding`, and no edits are
+/// returned when the texts are identical.
+pub fn compute_text_edits(
+    original: &str,
+    formatted: &str,
+    encoding: PositionEncoding,
+) -> Vec<TextEdit> {
+    if original == formatted {
+        return Vec::new();
+    }
+    let line_diff = TextDiff::from_lines(original, formatted);
+    let old_lines = line_diff.old_slices();
+    let new_lines = line_diff.new_slices();
+    let mut edits = Vec::new();
+    for op in line_diff.ops() {
+        if let DiffOp::Equal { .. } = op {
+            continue;
+        }
+        let old_range = op.old_range();
+        let new_range = op.new_range();
+        let old_hunk = old_lines[old_range.clone()].concat();
+        let new_hunk = new_lines[new_range].concat();
+        let start = Position::new(old_range.start as u32, 0);
+        let hunk_edits =
+            if old_hunk.chars().count().max(new_hunk.chars().count()) > MAX_CHAR_DIFF_LEN {
+                None
+            } else {
+                char_edits(start, &old_hunk, &new_hunk, encoding)
+            };
+        match hunk_edits {
+            Some(hunk_edits) => edits.extend(hunk_edits),
+            None => edits.push(TextEdit {
+                range: Range::new(start, advance(start, &old_hunk, encoding)),
+                new_text: new_hunk,
+            }),
+        }
+    }
+    edits
+}
+
+/// Diffs a hunk character by character, returning an edit for each change. `start` is the
+/// position of the hunk in the original document. Returns `None` when a change would start or end
+/// between the `\r` and `\n` of a line break, which has no position of its own (as when line
+/// endings change from `\r\n` to `\n`).
+fn char_edits(
+    start: Position,
+    old_hunk: &str,
+    new_hunk: &str,
+    encoding: PositionEncoding,
+) -> Option<Vec<TextEdit>> {
+    let char_diff = TextDiff::from_chars(old_hunk, new_hunk);
+    let old_chars = char_diff.old_slices();
+    let new_chars = char_diff.new_slices();
+    let splits_line_break = |index: usize| {
+        index > 0 && old_chars[index - 1] == "\r" && old_chars.get(index) == Some(&"\n")
+    };
+    if char_diff.ops().iter().any(|op| {
+        !matches!(op, DiffOp::Equal { .. })
+            && (splits_line_break(op.old_range().start) || splits_line_break(op.old_range().end))
+    }) {
+        return None;
+    }
+    let mut edits = Vec::new();
+    let mut position = start;
+    for op in char_diff.ops() {
+        let old_text = old_chars[op.old_range()].concat();
+        let end = advance(position, &old_text, encoding);
+        if !matches!(op, DiffOp::Equal { .. }) {
+            edits.push(TextEdit {
+                range: Range::new(position, end),
+                new_text: new_chars[op.new_range()].concat(),
+            });
+        }
+        position = end;
+    }
+    Some(edits)
+}
+
+/// Returns the position reached by moving past `text` from `position`. Lines end with `\n`,
+/// `\r\n` or `\r`.
+fn advance(mut position: Position, text: &str, encoding: PositionEncoding) -> Position {
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch == '\r' || ch == '\n' {
+            if ch == '\r' {
+                chars.next_if_eq(&'\n');
+            }
+            position.line += 1;
+            position.character = 0;
+        } else {
+            position.character += encoding.char_len(ch);
+        }
+    }
+    position
+}
+
+/// Maps a range of `original` onto `formatted`, following the edits that turn one into the other.
+/// A range boundary inside an edited span moves to the edge of its replacement, so that the range
+/// covers whatever replaced the text it used to cover.
+pub fn remap_range(
+    original: &str,
+    formatted: &str,
+    range: Range,
+    encoding: PositionEncoding,
+) -> Range {
+    let edits: Vec<(usize, usize, usize)> = compute_text_edits(original, formatted, encoding)
+        .into_iter()
+        .map(|edit| {
+            (
+                position_to_byte_offset(original, edit.range.start, encoding),
+                position_to_byte_offset(original, edit.range.end, encoding),
+                edit.new_text.len(),
+            )
+        })
+        .collect();
+    let remap = |position: Position, is_end: bool| {
+        let offset = position_to_byte_offset(original, position, encoding);
+        let mut remapped = offset;
+        for &(start, end, new_len) in &edits {
+            if end < offset || (end == offset && start < end) {
+                remapped = remapped + new_len - (end - start);
+            } else if start < offset {
+                remapped = remapped - (offset - start) + if is_end { new_len } else { 0 };
+                break;
+            } else {
+                break;
+            }
+        }
+        advance(Position::default(), &formatted[..remapped], encoding)
+    };
+    Range::new(remap(range.start, false), remap(range.end, true))
+}
+
+/// Converts a position (counted in `encoding`) into a byte offset into `text`, clamping it to the
+/// end of its line and the end of the text. Lines end with `\n`, `\r\n` or `\r`.
+pub fn position_to_byte_offset(
+    text: &str,
+    position: Position,
+    encoding: PositionEncoding,
+) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find(['\r', '\n']) {
+            Some(newline) => {
+                line_start += newline;
+                line_start += if text[line_start..].starts_with("\r\n") {
+                    2
+                } else {
+                    1
+                };
+            }
+            None => return text.len(),
+        }
+    }
+    let line = &text[line_start..];
+    let line = &line[..line.find(['\r', '\n']).unwrap_or(line.len())];
+    line_start + encoding.byte_offset(line, position.character)
+}
+
+/// Applies a unified diff (ie: the output of `diff -u` or `gofmt -d`) to `original`. Lines outside
+/// of hunks (file headers and the like) are ignored, so the diff should only cover one file.
+pub fn apply_unified_diff(original: &str, diff: &str) -> Result<String> {
+    let original_lines: Vec<&str> = original.split_inclusive('\n').collect();
+    let same_line =
+        |a: &str, b: &str| a.strip_suffix('\n').unwrap_or(a) == b.strip_suffix('\n').unwrap_or(b);
+    let mut output = String::new();
+    // The index of the first original line that hasn't been copied or removed yet.
+    let mut cursor = 0;
+    let mut diff_lines = diff.split_inclusive('\n').peekable();
+    while let Some(line) = diff_lines.next() {
+        let Some(header) = line.strip_prefix("@@ -") else {
+            continue;
+        };
+        let parse_range = |range: &str| -> Option<(usize, usize)> {
+            match range.split_once(',') {
+                Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+                None => Some((range.parse().ok()?, 1)),
+            }
+        };
+        let (old_range, new_range) = header
+            .split_once(" +")
+            .and_then(|(old_range, rest)| {
+                Some((
+                    parse_range(old_range)?,
+                    parse_range(rest.split_once(' ')?.0)?,
+                ))
+            })
+            .with_context(|| format!("invalid hunk header {line:?}"))?;
+        let (old_start, mut old_remaining) = old_range;
+        let (_, mut new_remaining) = new_range;
+        // Empty ranges name the line before the (empty) hunk.
+        let hunk_start = if old_remaining == 0 {
+            old_start
+        } else {
+            old_start.saturating_sub(1)
+        };
+        if hunk_start < cursor || hunk_start > original_lines.len() {
+            anyhow::bail!("hunk {line:?} does not fit the document");
+        }
+        output.extend(original_lines[cursor..hunk_start].iter().copied());
+        cursor = hunk_start;
+        let mut last_marker = "";
+        while old_remaining > 0
+            || new_remaining > 0
+            || diff_lines.peek().is_some_and(|line| line.starts_with('\\'))
+        {
+            let line = diff_lines.next().context("truncated hunk in diff")?;
+            let (marker, content) = line.split_at(line.chars().next().map_or(0, char::len_utf8));
+            match marker {
+                // Some tools drop the space of empty context lines.
+                " " | "-" | "\n" => {
+                    let original_line = original_lines
+                        .get(cursor)
+                        .filter(|original_line| same_line(original_line, content))
+                        .with_context(|| {
+                            format!("diff does not match the document at line {}", cursor + 1)
+                        })?;
+                    if marker != "-" {
+                        output.push_str(original_line);
+                        new_remaining = new_remaining.saturating_sub(1);
+                    }
+                    old_remaining = old_remaining.saturating_sub(1);
+                    cursor += 1;
+                }
+                "+" => {
+                    output.push_str(content);
+                    new_remaining = new_remaining.saturating_sub(1);
+                }
+                "\\" => {
+                    // "\ No newline at end of file" applies to the line before it. Removed and
+                    // context lines come from the document, which already lacks the newline.
+                    if last_marker == "+" {
+                        output.pop();
+                    }
+                }
+                _ => anyhow::bail!("unexpected line in hunk: {line:?}"),
+            }
+            last_marker = marker;
+        }
+    }
+    output.extend(original_lines[cursor..].iter().copied());
+    Ok(output)
+}
+
+/// Applies non-overlapping `edits` (in document order) to `text`.
+#[cfg(test)]
+fn apply_text_edits(text: &str, edits: &[TextEdit], encoding: PositionEncoding) -> String {
+    let offset = |position: Position| position_to_byte_offset(text, position, encoding);
+    let mut result = String::new();
+    let mut last = 0;
+    for edit in edits {
+        result.push_str(&text[last..offset(edit.range.start)]);
+        result.push_str(&edit.new_text);
+        last = offset(edit.range.end);
+    }
+    result.push_str(&text[last..]);
+    result
+}
+
+#[test]
+fn test_position_to_byte_offset() {
+    let text = "a\n\u{e9}\u{1f600}x\nlast";
+    let offset = |line, character, encoding| {
+        position_to_byte_offset(text, Position::new(line, character), encoding)
+    };
+    assert_eq!(offset(0, 0, PositionEncoding::Utf16), 0);
+    assert_eq!(offset(1, 1, PositionEncoding::Utf16), 4);
+    assert_eq!(offset(1, 3, PositionEncoding::Utf16), 8);
+    assert_eq!(offset(1, 99, PositionEncoding::Utf16), 9);
+    assert_eq!(offset(9, 0, PositionEncoding::Utf16), text.len());
+    assert_eq!(offset(1, 2, PositionEncoding::Utf32), 8);
+    assert_eq!(offset(1, 6, PositionEncoding::Utf8), 8);
+    // `\r\n` and a lone `\r` end lines too.
+    let text = "a\r\nb\rc";
+    let offset = |line, character| {
+        position_to_byte_offset(
+            text,
+            Position::new(line, character),
+            PositionEncoding::Utf16,
+        )
+    };
+    assert_eq!(offset(0, 9), 1);
+    assert_eq!(offset(1, 0), 3);
+    assert_eq!(offset(2, 1), 6);
+}
+
+#[test]
+fn test_apply_unified_diff() {
+    let original = "package main\nfunc main(){\n\tx:=1\n}\nlast";
+    let diff = "\
+diff -u main.go.orig main.go
+--- main.go.orig
++++ main.go
+@@ -1,4 +1,4 @@
+ package main
+-func main(){
+-\tx:=1
++func main() {
++\tx := 1
+ }
+@@ -5 +5 @@
+-last
+\\ No newline at end of file
++last
+";
+    assert_eq!(
+        apply_unified_diff(original, diff).unwrap(),
+        "package main\nfunc main() {\n\tx := 1\n}\nlast\n"
+    );
+    assert_eq!(apply_unified_diff(original, "").unwrap(), original);
+    // Insertions into an empty document, without a trailing newline.
+    let diff = "@@ -0,0 +1 @@\n+new\n\\ No newline at end of file\n";
+    assert_eq!(apply_unified_diff("", diff).unwrap(), "new");
+    // Diffs that don't match the document are rejected.
+    assert!(apply_unified_diff("other\n", "@@ -1 +1 @@\n-x\n+y\n").is_err());
+}
+
+#[test]
+fn test_compute_text_edits() {
+    assert!(compute_text_edits("a\nb\n", "a\nb\n", PositionEncoding::Utf16).is_empty());
+    let cases = [
+        (
+            "fn main(){\n  let x=1;\n}\n",
+            "fn main() {\n    let x = 1;\n}\n",
+        ),
+        ("a\nb\nc", "a\nc\nd\n"),
+        ("", "new\n"),
+        ("gone\n", ""),
+        (
+            "let s = \"\u{1f600}\" ;\r\nx\r\n",
+            "let s = \"\u{1f600}\";\r\nx\r\n",
+        ),
+        ("a\r\nb\r\n", "a\nb\n"),
+        ("a\nb\n", "a\r\nb\r\n"),
+        ("a\rb  \nc\n", "a\rb\nc\n"),
+    ];
+    for encoding in [
+        PositionEncoding::Utf8,
+        PositionEncoding::Utf16,
+        PositionEncoding::Utf32,
+    ] {
+        for (original, formatted) in cases {
+            let edits = compute_text_edits(original, formatted, encoding);
+            assert_eq!(apply_text_edits(original, &edits, encoding), formatted);
+        }
+    }
+    // Only the changed characters are touched, at positions in the negotiated encoding.
+    let space_edit = |encoding, character| {
+        let edits = compute_text_edits(
+            "let s = \"\u{1f600}\" ;\n",
+            "let s = \"\u{1f600}\";\n",
+            encoding,
+        );
+        assert_eq!(
+            edits,
+            vec![TextEdit {
+                range: Range::new(Position::new(0, character), Position::new(0, character + 1)),
+                new_text: String::new(),
+            }]
+        );
+    };
+    space_edit(PositionEncoding::Utf8, 14);
+    space_edit(PositionEncoding::Utf16, 12);
+    space_edit(PositionEncoding::Utf32, 11);
+    // Line endings that change from `\r\n` to `\n` replace whole lines, since there is no
+    // position between the `\r` and the `\n`.
+    assert_eq!(
+        compute_text_edits("a\r\nb\n", "a\nb\n", PositionEncoding::Utf16),
+        vec![TextEdit {
+            range: Range::new(Position::new(0, 0), Position::new(1, 0)),
+            new_text: "a\n".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn test_remap_range() {
+    let original = "fn a() {\n  x  =  1;\n}\nfn b() {\n  y=2;\n}\n";
+    let formatted = "fn a() {\n    x = 1;\n}\nfn b() {\n    y = 2;\n}\n";
+    let range = |start: (u32, u32), end: (u32, u32)| {
+        Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    };
+    let remap = |r| remap_range(original, formatted, r, PositionEncoding::Utf16);
+    // The body of `b` moved along with the reformatted text.
+    assert_eq!(remap(range((4, 0), (5, 0))), range((4, 0), (5, 0)));
+    assert_eq!(remap(range((4, 2), (4, 6))), range((4, 2), (4, 10)));
+    assert_eq!(remap(range((0, 0), (0, 8))), range((0, 0), (0, 8)));
+    // Boundaries within an edit cover all of its replacement.
+    let remapped = remap_range(
+        "let x = foo;\n",
+        "let x = barbaz;\n",
+        range((0, 9), (0, 10)),
+        PositionEncoding::Utf16,
+    );
+    assert_eq!(remapped, range((0, 8), (0, 14)));
+}
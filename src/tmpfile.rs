@@ -0,0 +1,76 @@
+use std::{
+    io::Write,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::prelude::*;
+
+static TMPFILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A copy of a document's contents written to disk for tools that cannot read from stdin. The
+/// copy keeps the document's basename (and thus its extension) so that tools which dispatch on
+/// filenames still recognize it. The file is removed when this is dropped.
+pub struct TmpFile {
+    path: PathBuf,
+    /// The private directory holding `path`, if one was created.
+    dir: Option<PathBuf>,
+}
+
+impl TmpFile {
+    /// Write `contents` to a new temp file named after `filename`. If `in_document_dir` is true,
+    /// the file is created as a hidden sibling of `filename` so that relative imports and
+    /// project configuration resolve the same way they would for the real file. Otherwise it is
+    /// created in a private directory under the system temp directory.
+    pub fn new(filename: &str, contents: &str, in_document_dir: bool) -> Result<Self> {
+        let filename = Path::new(filename);
+        let basename = filename
+            .file_name()
+            .context("document path has no file name")?
+            .to_string_lossy();
+        let unique = format!(
+            "pickls-{pid}-{counter}",
+            pid = std::process::id(),
+            counter = TMPFILE_COUNTER.fetch_add(1, Ordering::SeqCst)
+        );
+        let (path, dir) = if in_document_dir {
+            let parent = filename.parent().context("document path has no parent")?;
+            (parent.join(format!(".{unique}-{basename}")), None)
+        } else {
+            let dir = std::env::temp_dir().join(unique);
+            std::fs::create_dir(&dir)
+                .with_context(|| format!("creating temp directory {dir:?}"))?;
+            (dir.join(basename.as_ref()), Some(dir))
+        };
+        // Construct the guard first so that a failed write still cleans up after itself.
+        let tmpfile = Self { path, dir };
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmpfile.path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .with_context(|| format!("writing temp file {path:?}", path = tmpfile.path))?;
+        log::debug!("created temp file {path:?}", path = tmpfile.path);
+        Ok(tmpfile)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TmpFile {
+    fn drop(&mut self) {
+        if let Err(error) = std::fs::remove_file(&self.path) {
+            log::warn!(
+                "failed to remove temp file {path:?}: {error}",
+                path = self.path
+            );
+        }
+        if let Some(dir) = &self.dir
+            && let Err(error) = std::fs::remove_dir(dir)
+        {
+            log::warn!("failed to remove temp directory {dir:?}: {error}");
+        }
+    }
+}
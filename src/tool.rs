@@ -2,6 +2,7 @@ use std::{
     io::{BufRead, BufReader, Read, Write},
     os::unix::process::CommandExt as _,
     process::Child,
+    str::FromStr,
 };
 
 use nix::unistd::Pid;
@@ -49,96 +50,554 @@ fn get_root_dir(filename: &str, workspace: &Workspace, root_markers: &[String])
     Ok(basedir)
 }
 
-pub fn run_linter(
-    diagnostics_manager: &mut DiagnosticsManager,
-    linter_config: PicklsLinterConfig,
+/// Returns the nearest directory above `filename` that contains one of `root_markers`. The search
+/// stops at the workspace folder containing the file.
+pub fn find_root_marker(
+    filename: &str,
     workspace: &Workspace,
-    max_linter_count: usize,
-    file_content: Option<String>,
-    uri: Uri,
-    version: DocumentVersion,
-    language_root_markers: &[String],
-) -> Result<Pid> {
-    let (mut cmd, root_dir) = {
-        let filename = uri.path().as_str();
-
-        let mut cmd = Command::new(&linter_config.program);
-        let mut args = linter_config.args.clone();
-        for arg in args.iter_mut() {
-            *arg = arg.replace("$filename", filename);
-            log::trace!("arg after $filename replacement: {arg}");
-            // If the user specifies "$root(some_filename)" in the args, then replace it with
-            // the root directory determined by searching for root markers.
-            if let Some(start) = arg.find("$root(") {
-                if let Some(end) = arg[start..].find(')') {
-                    let marker_filename = &arg[start + 6..start + end];
-                    match get_root_dir(filename, workspace, &[marker_filename.to_string()]) {
-                        Ok(root_dir) => {
-                            log::debug!(
-                                "found root marker {marker_filename} near {filename}: {root_dir}"
-                            );
-                            *arg = arg.replace(&format!("$root({})", marker_filename), &root_dir);
-                        }
-                        Err(e) => log::warn!(
-                            "Could not find root marker {marker_filename} near {filename}: {e:?}"
-                        ),
-                    }
-                } else {
-                    log::warn!("unmatched $root( in arg: {arg}");
-                }
-            }
+    root_markers: &[String],
+) -> Option<PathBuf> {
+    let path = std::path::absolute(filename).ok()?;
+    for dir in path.ancestors().skip(1) {
+        if root_markers.iter().any(|marker| dir.join(marker).exists()) {
+            return Some(dir.to_path_buf());
         }
-        // Resolve effective root markers: use linter-specific if provided, otherwise inherit from language
-        let effective_root_markers = linter_config
-            .root_markers
-            .as_deref()
-            .unwrap_or(language_root_markers);
-        let root_dir: String = get_root_dir(filename, workspace, effective_root_markers)?;
+        if workspace.folders().any(|folder| folder.as_path() == dir) {
+            break;
+        }
+    }
+    None
+}
+
+/// Finds the executable that running `program` would run: `program` itself if it is a path, or
+/// the first match on `PATH` otherwise.
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt as _;
+    let is_executable = |path: &std::path::Path| {
+        path.metadata()
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    };
+    if program.contains('/') {
+        let path = PathBuf::from(program);
+        return is_executable(&path).then_some(path);
+    }
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| is_executable(path))
+}
+
+/// Whether a tool running `program` should be used on `filename`, given its `when` conditions.
+pub fn conditions_hold(
+    conditions: &PicklsConditions,
+    program: &str,
+    filename: &str,
+    workspace: &Workspace,
+) -> bool {
+    let unmet = |condition: &str| {
         log::info!(
-            "running linter {program} with root_dir={root_dir}",
-            program = linter_config.program
+            "skipping {program} for {filename} because its `{condition}` condition does not hold"
         );
-        cmd.process_group(0)
-            .args(args)
-            .current_dir(root_dir.clone())
-            .stdin(std::process::Stdio::piped());
-        if linter_config.use_stderr {
-            cmd.stdout(std::process::Stdio::null())
-                .stderr(std::process::Stdio::piped());
-        } else {
-            cmd.stdout(std::process::Stdio::piped())
-                .stderr(std::process::Stdio::null());
+        false
+    };
+    if !conditions.root_markers.is_empty()
+        && find_root_marker(filename, workspace, &conditions.root_markers).is_none()
+    {
+        return unmet("root_markers");
+    }
+    if !conditions.globs.is_empty() && !path_matches_globs(filename, workspace, &conditions.globs) {
+        return unmet("globs");
+    }
+    if conditions.program_exists && find_program(program).is_none() {
+        return unmet("program_exists");
+    }
+    if let Some(key) = &conditions.pyproject_key
+        && !project_file_has_key(filename, workspace, "pyproject.toml", key)
+    {
+        return unmet("pyproject_key");
+    }
+    if let Some(key) = &conditions.package_json_key
+        && !project_file_has_key(filename, workspace, "package.json", key)
+    {
+        return unmet("package_json_key");
+    }
+    true
+}
+
+/// Whether `filename` matches any of `globs`, either relative to its workspace folder or as an
+/// absolute path.
+fn path_matches_globs(filename: &str, workspace: &Workspace, globs: &[String]) -> bool {
+    let mut builder = globset::GlobSetBuilder::new();
+    for glob in globs {
+        match globset::GlobBuilder::new(glob)
+            .literal_separator(true)
+            .build()
+        {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(error) => log::error!("invalid glob [glob={glob}, error={error}]"),
+        }
+    }
+    let glob_set = match builder.build() {
+        Ok(glob_set) => glob_set,
+        Err(error) => {
+            log::error!("failed to compile globs [globs={globs:?}, error={error}]");
+            return false;
         }
-        (cmd, root_dir)
     };
-    log::info!("spawning {cmd:?}...");
-    let mut child: Child = cmd.spawn().with_context(|| format!("spawning ({cmd:?})"))?;
-    let child_pid = run_linter_core(
-        diagnostics_manager,
-        linter_config,
-        max_linter_count,
+    let Ok(path) = std::path::absolute(filename) else {
+        return false;
+    };
+    glob_set.is_match(&path)
+        || workspace
+            .folders()
+            .filter_map(|folder| path.strip_prefix(folder).ok())
+            .any(|relative_path| glob_set.is_match(relative_path))
+}
+
+/// Whether the nearest `project_file` (pyproject.toml or package.json) above `filename` contains
+/// the dot-separated `key`.
+fn project_file_has_key(
+    filename: &str,
+    workspace: &Workspace,
+    project_file: &str,
+    key: &str,
+) -> bool {
+    let Some(dir) = find_root_marker(filename, workspace, &[project_file.to_string()]) else {
+        return false;
+    };
+    let path = dir.join(project_file);
+    let contents = match read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(error) => {
+            log::error!("failed to read {path:?} [error={error}]");
+            return false;
+        }
+    };
+    let parsed = if project_file.ends_with(".toml") {
+        toml::from_str::<Value>(&contents).map_err(anyhow::Error::from)
+    } else {
+        serde_json::from_str::<Value>(&contents).map_err(anyhow::Error::from)
+    };
+    match parsed {
+        Ok(value) => lookup_json_path(&value, key).is_some(),
+        Err(error) => {
+            log::error!("failed to parse {path:?} [error={error}]");
+            false
+        }
+    }
+}
+
+#[test]
+fn test_conditions_hold() {
+    let root = std::env::temp_dir().join(format!("pickls-test-conditions-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("api/src")).unwrap();
+    std::fs::write(
+        root.join("api/pyproject.toml"),
+        "[tool.ruff]\nline-length = 100\n",
+    )
+    .unwrap();
+    std::fs::write(
+        root.join("package.json"),
+        r#"{"devDependencies": {"eslint": "9"}}"#,
+    )
+    .unwrap();
+    let mut workspace = Workspace::new();
+    workspace.add_folder(Uri::from_str(&format!("file://{}", root.display())).unwrap());
+    let filename = root.join("api/src/main.py");
+    let filename = filename.to_str().unwrap();
+    let check = |conditions: Value| {
+        let conditions: PicklsConditions = serde_json::from_value(conditions).unwrap();
+        conditions_hold(&conditions, "sh", filename, &workspace)
+    };
+    assert!(check(json!({})));
+    assert!(check(json!({"root_markers": ["pyproject.toml"]})));
+    assert!(!check(json!({"root_markers": [".flake8"]})));
+    assert!(check(json!({"globs": ["api/**/*.py"]})));
+    assert!(check(json!({"globs": ["**/src/*.py"]})));
+    assert!(!check(json!({"globs": ["api/*.py", "web/**"]})));
+    assert!(check(json!({"program_exists": true})));
+    assert!(check(json!({"pyproject_key": "tool.ruff"})));
+    assert!(!check(json!({"pyproject_key": "tool.black"})));
+    assert!(check(json!({"package_json_key": "devDependencies.eslint"})));
+    assert!(!check(
+        json!({"package_json_key": "devDependencies.prettier"})
+    ));
+    assert!(!check(
+        json!({"globs": ["api/**"], "pyproject_key": "tool.black"})
+    ));
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+/// Picks the formatters that will run on `filename`, skipping formatters whose conditions don't
+/// hold and choosing the first usable formatter of each group of alternatives.
+pub fn select_formatters(
+    entries: &[PicklsFormatterEntry],
+    filename: &str,
+    workspace: &Workspace,
+) -> Vec<PicklsFormatterConfig> {
+    entries
+        .iter()
+        .filter_map(|entry| match entry {
+            PicklsFormatterEntry::Formatter(formatter_config) => conditions_hold(
+                &formatter_config.when,
+                &formatter_config.program,
+                filename,
+                workspace,
+            )
+            .then(|| formatter_config.clone()),
+            PicklsFormatterEntry::Alternatives(alternatives) => {
+                let selected = alternatives.iter().find(|formatter_config| {
+                    find_program(&formatter_config.program).is_some()
+                        && conditions_hold(
+                            &formatter_config.when,
+                            &formatter_config.program,
+                            filename,
+                            workspace,
+                        )
+                        && formatter_config
+                            .root_markers
+                            .as_deref()
+                            .is_none_or(|root_markers| {
+                                root_markers.is_empty()
+                                    || find_root_marker(filename, workspace, root_markers).is_some()
+                            })
+                });
+                if selected.is_none() {
+                    log::info!(
+                        "no usable formatter among alternatives [programs={programs:?}]",
+                        programs = alternatives
+                            .iter()
+                            .map(|formatter_config| &formatter_config.program)
+                            .collect::<Vec<_>>()
+                    );
+                }
+                selected.cloned()
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_select_formatters() {
+    let entries: Vec<PicklsFormatterEntry> = serde_json::from_value(json!([
+        {"program": "cat"},
+        {"program": "cat", "when": {"globs": ["**/*.rs"]}},
+        {"alternatives": [
+            {"program": "pickls-test-missing-formatter"},
+            {"program": "sh", "root_markers": ["pickls-test-missing-marker"]},
+            {"program": "sh", "on_error": "skip"},
+        ]},
+        {"alternatives": [{"program": "pickls-test-missing-formatter"}]},
+    ]))
+    .unwrap();
+    let formatters = select_formatters(&entries, "/tmp/pickls-test/a.py", &Workspace::new());
+    assert_eq!(formatters.len(), 2);
+    assert_eq!(formatters[0].program, "cat");
+    assert_eq!(formatters[1].program, "sh");
+    assert_eq!(formatters[1].on_error, PicklsFormatterOnError::Skip);
+    let error = serde_json::from_value::<Vec<PicklsFormatterEntry>>(json!([{"args": []}]))
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("program"), "{error}");
+}
+
+/// Writes `file_content` to a temp file if any of `args` asks for one via "$tmpfilename".
+fn create_tmpfile_for_args(
+    args: &[String],
+    filename: &str,
+    file_content: &str,
+    in_document_dir: bool,
+) -> Result<Option<TmpFile>> {
+    if !args.iter().any(|arg| arg.contains("$tmpfilename")) {
+        return Ok(None);
+    }
+    TmpFile::new(filename, file_content, in_document_dir).map(Some)
+}
+
+/// Returns the directory that a linter runs in for `filename`: the nearest directory above it that
+/// contains one of the linter's root markers (or those of its language).
+pub fn linter_root_dir(
+    linter_config: &PicklsLinterConfig,
+    workspace: &Workspace,
+    filename: &str,
+    language_root_markers: &[String],
+) -> Result<String> {
+    // Resolve effective root markers: use linter-specific if provided, otherwise inherit from language
+    let effective_root_markers = linter_config
+        .root_markers
+        .as_deref()
+        .unwrap_or(language_root_markers);
+    get_root_dir(filename, workspace, effective_root_markers)
+}
+
+/// Builds the command that runs a linter on `filename`, along with the directory that it runs in
+/// and the temp file that it reads (if any).
+fn linter_command(
+    linter_config: &PicklsLinterConfig,
+    workspace: &Workspace,
+    filename: &str,
+    file_content: &str,
+    language_root_markers: &[String],
+) -> Result<(Command, String, Option<TmpFile>)> {
+    let tmpfile = create_tmpfile_for_args(
+        &linter_config.args,
+        filename,
         file_content,
-        uri,
-        version,
-        root_dir,
-        &mut child,
+        linter_config.tmpfile_in_document_dir,
+    )?;
+    let mut cmd = Command::new(&linter_config.program);
+    let mut args = linter_config.args.clone();
+    for arg in args.iter_mut() {
+        *arg = arg.replace("$filename", filename);
+        log::trace!("arg after $filename replacement: {arg}");
+        if let Some(tmpfile) = &tmpfile {
+            *arg = arg.replace("$tmpfilename", &tmpfile.path().to_string_lossy());
+        }
+        // If the user specifies "$root(some_filename)" in the args, then replace it with
+        // the root directory determined by searching for root markers.
+        if let Some(start) = arg.find("$root(") {
+            if let Some(end) = arg[start..].find(')') {
+                let marker_filename = &arg[start + 6..start + end];
+                match get_root_dir(filename, workspace, &[marker_filename.to_string()]) {
+                    Ok(root_dir) => {
+                        log::debug!(
+                            "found root marker {marker_filename} near {filename}: {root_dir}"
+                        );
+                        *arg = arg.replace(&format!("$root({})", marker_filename), &root_dir);
+                    }
+                    Err(e) => log::warn!(
+                        "Could not find root marker {marker_filename} near {filename}: {e:?}"
+                    ),
+                }
+            } else {
+                log::warn!("unmatched $root( in arg: {arg}");
+            }
+        }
+    }
+    let root_dir = linter_root_dir(linter_config, workspace, filename, language_root_markers)?;
+    log::info!(
+        "running linter {program} with root_dir={root_dir}",
+        program = linter_config.program
     );
-    let _ = child
-        .wait()
-        .inspect_err(|e| panic!("Failed to wait for child process: {child:?}: {e}"));
-    child_pid
+    cmd.process_group(0)
+        .args(args)
+        .current_dir(root_dir.clone())
+        .stdin(std::process::Stdio::piped());
+    if linter_config.use_stderr {
+        cmd.stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped());
+    } else {
+        cmd.stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null());
+    }
+    Ok((cmd, root_dir, tmpfile))
+}
+
+/// The diagnostic reported in place of a linter's output when it times out. Whatever the linter
+/// managed to print before it was killed is likely incomplete.
+fn timeout_diagnostic(
+    linter_config: &PicklsLinterConfig,
+    timeout_ms: u64,
+    uri: &Uri,
+    position_encoding: PositionEncoding,
+) -> Diagnostic {
+    PicklsDiagnostic {
+        linter: linter_config.program.clone(),
+        filename: uri.path().to_string(),
+        line: 1,
+        start_column: None,
+        end_line: None,
+        end_column: None,
+        severity: Some(PicklsDiagnosticSeverity {
+            severity: "warning".to_string(),
+        }),
+        code: None,
+        description: Some(format!(
+            "{program} timed out after {timeout_ms}ms",
+            program = linter_config.program
+        )),
+        related: Vec::new(),
+        fix: None,
+    }
+    .into_diagnostic(&[], ColumnMapping::new(linter_config, position_encoding))
 }
 
-fn run_linter_core(
-    diagnostics_manager: &mut DiagnosticsManager,
+pub fn run_linter(
+    rt: &Runtime,
+    background_sender: Sender<BackgroundMessage>,
+    job_id: JobId,
     linter_config: PicklsLinterConfig,
+    workspace: &Workspace,
     max_linter_count: usize,
-    file_content: Option<String>,
+    file_content: String,
     uri: Uri,
     version: DocumentVersion,
-    root_dir: String,
+    language_root_markers: &[String],
+    min_severity: Option<PicklsSeverity>,
+    position_encoding: PositionEncoding,
+) -> Result<ProcessGroup> {
+    let (mut cmd, root_dir, tmpfile) = linter_command(
+        &linter_config,
+        workspace,
+        uri.path().as_str(),
+        &file_content,
+        language_root_markers,
+    )?;
+    log::info!("spawning {cmd:?}...");
+    let mut child: Child = cmd.spawn().with_context(|| format!("spawning ({cmd:?})"))?;
+    let child_pid = Pid::from_raw(child.id() as i32);
+    let process_group = ProcessGroup::new(child_pid);
+    let reaped_process_group = process_group.clone();
+    // Feeding stdin and parsing output can take as long as the linter itself, so that happens on
+    // the blocking pool. The results are posted back to the main loop when the linter exits.
+    rt.spawn_blocking(move || {
+        let watchdog = linter_config
+            .timeout_ms
+            .map(|timeout_ms| Watchdog::spawn(child_pid, Duration::from_millis(timeout_ms)));
+        let disarm = || {
+            watchdog.iter().for_each(Watchdog::disarm);
+            reaped_process_group.set_reaped();
+        };
+        let mut diagnostics = run_linter_core(
+            &linter_config,
+            &file_content,
+            &mut child,
+            disarm,
+            |output| {
+                ingest_linter_errors(
+                    &uri,
+                    &file_content,
+                    &root_dir,
+                    tmpfile.as_ref(),
+                    min_severity,
+                    position_encoding,
+                    &linter_config,
+                    output,
+                )
+            },
+        );
+        // The linter has exited (or was killed), so its copy of the document can go.
+        drop(tmpfile);
+        if let Some(timeout_ms) = linter_config.timeout_ms
+            && watchdog.is_some_and(|watchdog| watchdog.fired())
+        {
+            diagnostics = Ok(vec![timeout_diagnostic(
+                &linter_config,
+                timeout_ms,
+                &uri,
+                position_encoding,
+            )]);
+        }
+        let output = LinterOutput {
+            job_id,
+            pid: child_pid,
+            run_on: linter_config.run_on,
+            linter_name: linter_config.program,
+            max_linter_count,
+            uri,
+            version,
+            diagnostics,
+        };
+        if background_sender
+            .send(BackgroundMessage::LinterFinished(output))
+            .is_err()
+        {
+            log::warn!("main loop is gone, dropping linter output [pid={child_pid}]");
+        }
+    });
+    Ok(process_group)
+}
+
+/// Runs a workspace-scoped linter over the project that `uri` belongs to. Unlike `run_linter`, the
+/// diagnostics of every file that the linter reports on are kept.
+pub fn run_workspace_linter(
+    rt: &Runtime,
+    background_sender: Sender<BackgroundMessage>,
+    job_id: WorkspaceJobId,
+    linter_config: PicklsLinterConfig,
+    workspace: &Workspace,
+    file_content: String,
+    uri: Uri,
+    open_documents: OpenDocuments,
+    language_root_markers: &[String],
+    min_severity: Option<PicklsSeverity>,
+    position_encoding: PositionEncoding,
+) -> Result<ProcessGroup> {
+    let (mut cmd, root_dir, tmpfile) = linter_command(
+        &linter_config,
+        workspace,
+        uri.path().as_str(),
+        &file_content,
+        language_root_markers,
+    )?;
+    log::info!("spawning {cmd:?}...");
+    let mut child: Child = cmd.spawn().with_context(|| format!("spawning ({cmd:?})"))?;
+    let child_pid = Pid::from_raw(child.id() as i32);
+    let process_group = ProcessGroup::new(child_pid);
+    let reaped_process_group = process_group.clone();
+    rt.spawn_blocking(move || {
+        let watchdog = linter_config
+            .timeout_ms
+            .map(|timeout_ms| Watchdog::spawn(child_pid, Duration::from_millis(timeout_ms)));
+        let disarm = || {
+            watchdog.iter().for_each(Watchdog::disarm);
+            reaped_process_group.set_reaped();
+        };
+        let mut diagnostics = run_linter_core(
+            &linter_config,
+            &file_content,
+            &mut child,
+            disarm,
+            |output| {
+                ingest_workspace_linter_errors(
+                    &uri,
+                    &file_content,
+                    &open_documents,
+                    &root_dir,
+                    tmpfile.as_ref(),
+                    min_severity,
+                    position_encoding,
+                    &linter_config,
+                    output,
+                )
+            },
+        );
+        drop(tmpfile);
+        if let Some(timeout_ms) = linter_config.timeout_ms
+            && watchdog.is_some_and(|watchdog| watchdog.fired())
+        {
+            // The timeout is reported on the document that triggered the run, and replaces the
+            // diagnostics of the previous run everywhere else.
+            let diagnostic =
+                timeout_diagnostic(&linter_config, timeout_ms, &uri, position_encoding);
+            diagnostics = Ok(HashMap::from([(uri.clone(), vec![diagnostic])]));
+        }
+        let output = WorkspaceLinterOutput {
+            job_id,
+            pid: child_pid,
+            uri,
+            diagnostics,
+        };
+        if background_sender
+            .send(BackgroundMessage::WorkspaceLinterFinished(output))
+            .is_err()
+        {
+            log::warn!("main loop is gone, dropping linter output [pid={child_pid}]");
+        }
+    });
+    Ok(process_group)
+}
+
+/// Feeds the document to the linter (if it reads stdin), ingests its output and waits for it to
+/// exit. `disarm` runs right before the linter is reaped (see `reap`).
+fn run_linter_core<T>(
+    linter_config: &PicklsLinterConfig,
+    file_content: &str,
     child: &mut Child,
-) -> Result<Pid> {
+    disarm: impl FnOnce(),
+    ingest: impl FnOnce(BufReader<Box<dyn Read>>) -> Result<T>,
+) -> Result<T> {
     log::info!(
         "{program} PID={pid}",
         program = linter_config.program,
@@ -146,43 +605,33 @@ fn run_linter_core(
     );
     if linter_config.use_stdin {
         let mut stdin: std::process::ChildStdin = child.stdin.take().expect("Failed to open stdin");
-        if let Some(file_content) = file_content {
-            log::info!(
-                "writing to `{program}`'s stdin: '{preamble}...'",
-                program = linter_config.program,
-                preamble = String::from(&file_content[..std::cmp::min(file_content.len(), 20)])
-                    .replace("\n", "\\n")
+        log::info!(
+            "writing to `{program}`'s stdin: '{preamble}...'",
+            program = linter_config.program,
+            preamble = String::from(&file_content[..std::cmp::min(file_content.len(), 20)])
+                .replace("\n", "\\n")
+        );
+        if let Err(error) = stdin.write_all(file_content.as_bytes()) {
+            // The linter may have exited (or been killed) without reading all of its input.
+            // Whatever it wrote is still worth ingesting.
+            log::warn!(
+                "failed to write to `{program}`'s stdin: {error}",
+                program = linter_config.program
             );
-            stdin.write_all(file_content.as_bytes())?;
         }
         drop(stdin);
     }
     let child_pid = Pid::from_raw(child.id() as i32);
-    if let Err(error) = if linter_config.use_stderr {
-        ingest_linter_errors(
-            uri,
-            version,
-            diagnostics_manager,
-            &root_dir,
-            max_linter_count,
-            &linter_config,
-            BufReader::new(child.stderr.take().expect("Failed to take stderr")),
-        )
+    let output: Box<dyn Read> = if linter_config.use_stderr {
+        Box::new(child.stderr.take().expect("Failed to take stderr"))
     } else {
-        ingest_linter_errors(
-            uri,
-            version,
-            diagnostics_manager,
-            &root_dir,
-            max_linter_count,
-            &linter_config,
-            BufReader::new(child.stdout.take().expect("Failed to take stdout")),
-        )
-    } {
+        Box::new(child.stdout.take().expect("Failed to take stdout"))
+    };
+    let diagnostics = ingest(BufReader::new(output));
+    if let Err(error) = &diagnostics {
         log::error!("[run_linter/spawn-ingest] error: {error:?}");
     }
-    child
-        .wait()
+    reap(child, disarm)
         .inspect(|status| {
             log::info!(
                 "linter program '{program}' exited with status: {status:?} [pid={pid}]",
@@ -196,7 +645,7 @@ fn run_linter_core(
                 program = linter_config.program,
             )
         })?;
-    Ok(child_pid)
+    diagnostics
 }
 
 fn convert_capture_to_diagnostic(
@@ -210,7 +659,7 @@ fn convert_capture_to_diagnostic(
         None => None,
         Some(-1) => prior_line.as_ref().map(|s| s.trim().to_string()),
         Some(i) if i > 0 => {
-            if linter_config.line_match >= caps_len {
+            if i as usize >= caps_len {
                 log::error!(
                     "invalid description_match in linter configuration of `{program}`: pattern only captures {caps_len} groups but description_match = {i}.",
                     program = linter_config.program
@@ -231,33 +680,59 @@ fn convert_capture_to_diagnostic(
         .filename_match
         .and_then(|i| caps.get(i).map(|x| x.as_str().to_string()))
         .unwrap_or_else(|| absolute_filename.to_string());
-    if linter_config.line_match >= caps_len {
-        log::error!(
-            "invalid line_match in linter configuration of `{program}`: pattern only captures {caps_len} groups but line_match = {line_match}.",
-            line_match = linter_config.line_match,
-            program = linter_config.program
-        );
-        return None;
-    }
-    let line: u32 = caps
-        .get(linter_config.line_match)
-        .unwrap()
-        .as_str()
-        .parse()
-        .ok()?;
+    // In multi-line mode the location may only show up on a later `location` line. Until then, the
+    // diagnostic sits on (the otherwise invalid) line 0.
+    let multiline = linter_config.multiline.is_some();
+    let line: u32 = match linter_config.line_match {
+        Some(line_match) if line_match >= caps_len => {
+            log::error!(
+                "invalid line_match in linter configuration of `{program}`: pattern only captures {caps_len} groups but line_match = {line_match}.",
+                program = linter_config.program
+            );
+            return None;
+        }
+        Some(line_match) => match caps.get(line_match).and_then(|m| m.as_str().parse().ok()) {
+            Some(line) => line,
+            None if multiline => 0,
+            None => return None,
+        },
+        None if multiline => 0,
+        None => {
+            log::error!(
+                "missing line_match in linter configuration of `{program}`",
+                program = linter_config.program
+            );
+            return None;
+        }
+    };
     let start_column = linter_config
         .start_col_match
         .and_then(|i| caps.get(i)?.as_str().parse().ok());
+    let end_line = linter_config
+        .end_line_match
+        .and_then(|i| caps.get(i)?.as_str().parse().ok());
     let end_column = linter_config
         .end_col_match
         .and_then(|i| caps.get(i)?.as_str().parse().ok());
+    let code = linter_config
+        .code_match
+        .and_then(|i| Some(caps.get(i)?.as_str().to_string()));
+    let fix = linter_config.fix_match.and_then(|i| {
+        Some(PicklsFix {
+            title: None,
+            edits: vec![PicklsFixEdit {
+                range: PicklsFixRange::Diagnostic,
+                new_text: caps.get(i)?.as_str().to_string(),
+            }],
+        })
+    });
     let severity: Option<PicklsDiagnosticSeverity> = linter_config.severity_match.and_then(|i| {
         Some(PicklsDiagnosticSeverity {
             severity: caps.get(i)?.as_str().to_string(),
         })
     });
     log::debug!(
-        "captured diagnostic: filename={filename}, line={line}, start_column={start_column:?}, end_column={end_column:?}, severity={severity:?}, description={description:?} cwd={}",
+        "captured diagnostic: filename={filename}, line={line}, start_column={start_column:?}, end_line={end_line:?}, end_column={end_column:?}, severity={severity:?}, code={code:?}, description={description:?} cwd={}",
         std::env::current_dir().unwrap().display()
     );
     Some(PicklsDiagnostic {
@@ -265,117 +740,611 @@ fn convert_capture_to_diagnostic(
         filename,
         line,
         start_column,
+        end_line,
         end_column,
         severity,
+        code,
         description,
+        related: Vec::new(),
+        fix,
     })
 }
 
+/// Parses the diagnostics out of a linter's output according to its `output_format`.
+fn parse_linter_output(
+    uri: &Uri,
+    linter_config: &PicklsLinterConfig,
+    mut child_stdout: BufReader<impl Read>,
+) -> Result<Vec<PicklsDiagnostic>> {
+    Ok(match linter_config.output_format {
+        PicklsOutputFormat::Regex => match &linter_config.errorformat {
+            Some(errorformat) => {
+                parse_errorformat_output(uri, linter_config, errorformat, child_stdout)?
+            }
+            None => parse_regex_output(uri, linter_config, child_stdout)?,
+        },
+        output_format => {
+            let mut output = Vec::new();
+            child_stdout.read_to_end(&mut output)?;
+            let output = String::from_utf8_lossy(&strip_ansi_escapes::strip(output)).to_string();
+            parse_structured_output(output_format, linter_config, &output, &uri_to_path(uri))?
+        }
+    })
+}
+
+/// Returns the real path of the file that a document is stored in.
+pub fn realpath_for_uri(uri: &Uri) -> Result<PathBuf> {
+    let path = uri_to_path(uri);
+    match std::fs::canonicalize(&path) {
+        Ok(path) => Ok(path),
+        Err(_) => {
+            // Fallback to using an absolute path if canonicalization fails. It may fail if the
+            // file temporarily doesn't exist due to unlink + move operations that some editors
+            // (ie: neovim perform.)
+            Ok(std::path::absolute(path)?)
+        }
+    }
+}
+
+/// Returns the real path of the file that a diagnostic is about. Relative filenames are relative
+/// to the directory that the linter ran in.
+fn realpath_for_diagnostic(filename: &str, root_dir: &std::path::Path) -> Option<PathBuf> {
+    let mut path = PathBuf::from(filename);
+    if path.is_relative() {
+        path = root_dir.join(path);
+    }
+    path.canonicalize()
+        .inspect_err(|e| {
+            log::warn!(
+                "could not canonicalize path {path:?} for diagnostic: {e} cwd={cwd:?} root_dir={root_dir}",
+                cwd = std::env::current_dir(),
+                root_dir = root_dir.display()
+            );
+        })
+        .ok()
+}
+
+/// Converts a diagnostic about `uri` into an LSP diagnostic. Returns None if it is less severe than
+/// `min_severity`.
+fn convert_linter_diagnostic(
+    mut pickls_diagnostic: PicklsDiagnostic,
+    uri: &Uri,
+    document_lines: &[&str],
+    root_dir: &std::path::Path,
+    tmpfile: Option<&TmpFile>,
+    realpath_for_tmpfile: Option<&std::path::Path>,
+    columns: ColumnMapping,
+    severity_resolver: &SeverityResolver,
+    min_severity: Option<DiagnosticSeverity>,
+    linter_config: &PicklsLinterConfig,
+) -> Option<Diagnostic> {
+    let severity = severity_resolver.resolve(pickls_diagnostic.severity.as_ref());
+    // Note that more severe diagnostics have lower values.
+    if let (Some(severity), Some(min_severity)) = (severity, min_severity)
+        && severity > min_severity
+    {
+        log::trace!("dropping diagnostic below min_severity [severity={severity:?}]");
+        return None;
+    }
+    if let Some(tmpfile) = tmpfile {
+        // Don't leak the name of the temp file into messages shown to the user.
+        pickls_diagnostic.description = pickls_diagnostic.description.map(|description| {
+            description.replace(
+                tmpfile.path().to_string_lossy().as_ref(),
+                uri.path().as_str(),
+            )
+        });
+    }
+    let related = std::mem::take(&mut pickls_diagnostic.related);
+    let mut lsp_diagnostic = pickls_diagnostic.into_diagnostic(document_lines, columns);
+    lsp_diagnostic.severity = severity;
+    if !related.is_empty() {
+        lsp_diagnostic.related_information = Some(
+            related
+                .into_iter()
+                .filter_map(|related| {
+                    convert_related_location(
+                        related,
+                        uri,
+                        root_dir,
+                        realpath_for_tmpfile,
+                        document_lines,
+                        columns,
+                    )
+                })
+                .collect(),
+        );
+    }
+    if let Some(code_url_template) = &linter_config.code_url_template {
+        lsp_diagnostic.code_description = render_code_description(
+            code_url_template,
+            &linter_config.program,
+            lsp_diagnostic.code.as_ref(),
+        );
+    }
+    Some(lsp_diagnostic)
+}
+
 fn ingest_linter_errors(
-    uri: Uri,
-    version: DocumentVersion,
-    diagnostics_manager: &mut DiagnosticsManager,
+    uri: &Uri,
+    file_content: &str,
     root_dir: &str,
-    max_linter_count: usize,
+    tmpfile: Option<&TmpFile>,
+    min_severity: Option<PicklsSeverity>,
+    position_encoding: PositionEncoding,
     linter_config: &PicklsLinterConfig,
     child_stdout: BufReader<impl Read>,
-) -> Result<()> {
-    let re = Regex::new(&linter_config.pattern).with_context(|| {
-        format!(
-            "invalid regex [pattern={pattern}]",
-            pattern = linter_config.pattern
-        )
-    })?;
+) -> Result<Vec<Diagnostic>> {
+    let pickls_diagnostics = parse_linter_output(uri, linter_config, child_stdout)?;
     let mut lsp_diagnostics: Vec<Diagnostic> = Default::default();
-    let mut prior_line: Option<String> = None;
     let root_dir = std::path::PathBuf::from(root_dir);
-    let realpath_for_uri = match std::fs::canonicalize(uri.path().as_str()) {
-        Ok(path) => path,
-        Err(_) => {
-            // Fallback to using an absolute path if canonicalization fails. It may fail if the
-            // file temporarily doesn't exist due to unlink + move operations that some editors
-            // (ie: neovim perform.)
-            std::path::absolute(uri.path().as_str())?
+    let realpath_for_uri = realpath_for_uri(uri)?;
+    let realpath_for_tmpfile = tmpfile.and_then(|tmpfile| tmpfile.path().canonicalize().ok());
+    let document_lines: Vec<&str> = file_content.split('\n').collect();
+    let columns = ColumnMapping::new(linter_config, position_encoding);
+    let severity_resolver = SeverityResolver::new(linter_config)?;
+    let min_severity = min_severity.map(DiagnosticSeverity::from);
+    for pickls_diagnostic in pickls_diagnostics {
+        // log::info!("diagnostic: {pickls_diagnostic:?}");
+        let Some(realpath_for_diagnostic) =
+            realpath_for_diagnostic(&pickls_diagnostic.filename, &root_dir)
+        else {
+            continue;
+        };
+        if realpath_for_uri == realpath_for_diagnostic
+            || realpath_for_tmpfile.as_ref() == Some(&realpath_for_diagnostic)
+        {
+            // Note that this filtering can be avoided in cases that the linter is known to only
+            // lint in the current file. This is intended to filter out diagnostics from linters
+            // that scan multiple files. Those can use `scope: workspace` to keep them instead.
+            lsp_diagnostics.extend(convert_linter_diagnostic(
+                pickls_diagnostic,
+                uri,
+                &document_lines,
+                &root_dir,
+                tmpfile,
+                realpath_for_tmpfile.as_deref(),
+                columns,
+                &severity_resolver,
+                min_severity,
+                linter_config,
+            ));
+        } else {
+            log::warn!(
+                "ignoring diagnostic for {uri} because it is not in the current document [filename={realpath_for_diagnostic:?}]",
+                uri = uri.as_str(),
+            );
         }
+    }
+    log::info!(
+        "parsed diagnostics [linter={linter_name}, count={count}]",
+        linter_name = linter_config.program,
+        count = lsp_diagnostics.len()
+    );
+    Ok(lsp_diagnostics)
+}
+
+/// The open documents of a project by real path, along with the uri that the client opened each
+/// one as and its current text.
+pub type OpenDocuments = HashMap<PathBuf, (Uri, String)>;
+
+/// Ingests the output of a workspace-scoped linter, keeping the diagnostics of every file that it
+/// reports on. Files that aren't open are read from disk so that their columns can be mapped.
+fn ingest_workspace_linter_errors(
+    uri: &Uri,
+    file_content: &str,
+    open_documents: &OpenDocuments,
+    root_dir: &str,
+    tmpfile: Option<&TmpFile>,
+    min_severity: Option<PicklsSeverity>,
+    position_encoding: PositionEncoding,
+    linter_config: &PicklsLinterConfig,
+    child_stdout: BufReader<impl Read>,
+) -> Result<HashMap<Uri, Vec<Diagnostic>>> {
+    let pickls_diagnostics = parse_linter_output(uri, linter_config, child_stdout)?;
+    let root_dir = std::path::PathBuf::from(root_dir);
+    let realpath_for_uri = realpath_for_uri(uri)?;
+    let realpath_for_tmpfile = tmpfile.and_then(|tmpfile| tmpfile.path().canonicalize().ok());
+    let columns = ColumnMapping::new(linter_config, position_encoding);
+    let severity_resolver = SeverityResolver::new(linter_config)?;
+    let min_severity = min_severity.map(DiagnosticSeverity::from);
+    // Group the diagnostics by file first, so that each file is only read once.
+    let mut diagnostics_by_path: HashMap<PathBuf, Vec<PicklsDiagnostic>> = Default::default();
+    for pickls_diagnostic in pickls_diagnostics {
+        let Some(mut path) = realpath_for_diagnostic(&pickls_diagnostic.filename, &root_dir) else {
+            continue;
+        };
+        if realpath_for_tmpfile.as_ref() == Some(&path) {
+            path = realpath_for_uri.clone();
+        }
+        diagnostics_by_path
+            .entry(path)
+            .or_default()
+            .push(pickls_diagnostic);
+    }
+    let mut lsp_diagnostics: HashMap<Uri, Vec<Diagnostic>> = Default::default();
+    for (path, pickls_diagnostics) in diagnostics_by_path {
+        // The document that triggered the run, like any other open document, keeps the client's
+        // uri (which needn't be the canonical one), so that its diagnostics line up with the
+        // documents that the client has open.
+        let (file_uri, file_content) = if path == realpath_for_uri {
+            (uri.clone(), file_content.to_string())
+        } else if let Some((open_uri, open_content)) = open_documents.get(&path) {
+            (open_uri.clone(), open_content.clone())
+        } else {
+            let file_uri = match path_to_uri(&path) {
+                Ok(file_uri) => file_uri,
+                Err(error) => {
+                    log::warn!("ignoring diagnostics for {path:?}: {error:#}");
+                    continue;
+                }
+            };
+            let file_content = std::fs::read_to_string(&path).unwrap_or_else(|error| {
+                log::warn!("could not read {path:?} to map its diagnostics: {error}");
+                String::new()
+            });
+            (file_uri, file_content)
+        };
+        let document_lines: Vec<&str> = file_content.split('\n').collect();
+        let diagnostics = pickls_diagnostics
+            .into_iter()
+            .filter_map(|pickls_diagnostic| {
+                convert_linter_diagnostic(
+                    pickls_diagnostic,
+                    &file_uri,
+                    &document_lines,
+                    &root_dir,
+                    tmpfile,
+                    realpath_for_tmpfile.as_deref(),
+                    columns,
+                    &severity_resolver,
+                    min_severity,
+                    linter_config,
+                )
+            })
+            .collect();
+        lsp_diagnostics.insert(file_uri, diagnostics);
+    }
+    log::info!(
+        "parsed workspace diagnostics [linter={linter_name}, files={files}, count={count}]",
+        linter_name = linter_config.program,
+        files = lsp_diagnostics.len(),
+        count = lsp_diagnostics.values().map(Vec::len).sum::<usize>()
+    );
+    Ok(lsp_diagnostics)
+}
+
+fn convert_related_location(
+    related: PicklsRelatedLocation,
+    uri: &Uri,
+    root_dir: &std::path::Path,
+    realpath_for_tmpfile: Option<&std::path::Path>,
+    document_lines: &[&str],
+    columns: ColumnMapping,
+) -> Option<DiagnosticRelatedInformation> {
+    let mut path = PathBuf::from(&related.filename);
+    if path.is_relative() {
+        path = root_dir.join(path);
+    }
+    let path = path.canonicalize().unwrap_or(path);
+    let location_uri = if realpath_for_tmpfile == Some(path.as_path()) {
+        uri.clone()
+    } else {
+        path_to_uri(&path)
+            .inspect_err(|error| log::warn!("invalid related location: {error:#}"))
+            .ok()?
+    };
+    let line = related.line.saturating_sub(1);
+    let column = related
+        .start_column
+        .map_or(0, |column| column.saturating_sub(columns.start_at));
+    // The text of other files isn't at hand, so their columns are used as they are.
+    let character = if location_uri == *uri {
+        columns.character(document_lines, line, column)
+    } else {
+        column
+    };
+    Some(DiagnosticRelatedInformation {
+        location: Location {
+            uri: location_uri,
+            range: Range {
+                start: Position { line, character },
+                end: Position {
+                    line,
+                    character: character + 1,
+                },
+            },
+        },
+        message: related.description.unwrap_or_default(),
+    })
+}
+
+/// The context of templates that are rendered for a diagnostic's rule code.
+#[derive(Serialize)]
+pub struct CodeTemplateContext<'a> {
+    pub code: &'a str,
+    pub linter: &'a str,
+}
+
+fn render_code_description(
+    code_url_template: &str,
+    linter: &str,
+    code: Option<&NumberOrString>,
+) -> Option<CodeDescription> {
+    let code = match code? {
+        NumberOrString::String(code) => code.clone(),
+        NumberOrString::Number(code) => code.to_string(),
     };
+    let href = render_template(
+        code_url_template,
+        CodeTemplateContext {
+            code: &code,
+            linter,
+        },
+    )
+    .inspect_err(|error| log::warn!("invalid code_url_template for {linter}: {error}"))
+    .ok()?;
+    match Uri::from_str(&href) {
+        Ok(href) => Some(CodeDescription { href }),
+        Err(error) => {
+            log::warn!("code_url_template for {linter} rendered an invalid url '{href}': {error}");
+            None
+        }
+    }
+}
+
+/// The compiled form of a linter's `multiline` configuration.
+struct MultilinePatterns<'a> {
+    config: &'a PicklsMultilineConfig,
+    continuation: Regex,
+    end: Option<Regex>,
+    location: Option<Regex>,
+}
+
+impl<'a> MultilinePatterns<'a> {
+    fn new(config: &'a PicklsMultilineConfig) -> Result<Self> {
+        let compile = |pattern: &str| {
+            Regex::new(pattern).with_context(|| format!("invalid regex [pattern={pattern}]"))
+        };
+        Ok(Self {
+            config,
+            continuation: compile(&config.continuation)?,
+            end: config.end.as_deref().map(compile).transpose()?,
+            location: config
+                .location
+                .as_ref()
+                .map(|location| compile(&location.pattern))
+                .transpose()?,
+        })
+    }
+
+    /// Folds `line` into `diagnostic` if it belongs to it. Returns whether the line was consumed
+    /// and whether it ended the diagnostic.
+    fn continue_diagnostic(
+        &self,
+        diagnostic: &mut PicklsDiagnostic,
+        line: &str,
+        absolute_filename: &str,
+    ) -> (bool, bool) {
+        if let Some(caps) = self.end.as_ref().and_then(|end| end.captures(line)) {
+            append_description(diagnostic, caps.get(1).map(|m| m.as_str()));
+            return (true, true);
+        }
+        if let (Some(location), Some(caps)) = (
+            &self.config.location,
+            self.location.as_ref().and_then(|re| re.captures(line)),
+        ) && let Some(line) = caps
+            .get(location.line_match)
+            .and_then(|m| m.as_str().parse().ok())
+        {
+            let filename = location
+                .filename_match
+                .and_then(|i| caps.get(i))
+                .map_or_else(|| absolute_filename.to_string(), |m| m.as_str().to_string());
+            let start_column = location
+                .start_col_match
+                .and_then(|i| caps.get(i)?.as_str().parse().ok());
+            let description = location
+                .description_match
+                .and_then(|i| caps.get(i))
+                .map(|m| m.as_str().to_string());
+            if diagnostic.line == 0 {
+                // The first location of a diagnostic whose header didn't have one is its own.
+                diagnostic.filename = filename;
+                diagnostic.line = line;
+                diagnostic.start_column = start_column;
+                append_description(diagnostic, description.as_deref());
+            } else {
+                diagnostic.related.push(PicklsRelatedLocation {
+                    filename,
+                    line,
+                    start_column,
+                    description,
+                });
+            }
+            return (true, false);
+        }
+        if let Some(caps) = self.continuation.captures(line) {
+            let text = caps.get(1).or_else(|| caps.get(0)).map(|m| m.as_str());
+            append_description(diagnostic, text);
+            return (true, false);
+        }
+        (false, true)
+    }
+}
+
+fn append_description(diagnostic: &mut PicklsDiagnostic, text: Option<&str>) {
+    let Some(text) = text
+        .map(str::trim_end)
+        .filter(|text| !text.trim().is_empty())
+    else {
+        return;
+    };
+    match &mut diagnostic.description {
+        Some(description) => {
+            description.push('\n');
+            description.push_str(text);
+        }
+        None => diagnostic.description = Some(text.trim().to_string()),
+    }
+}
+
+fn parse_regex_output(
+    uri: &Uri,
+    linter_config: &PicklsLinterConfig,
+    child_stdout: BufReader<impl Read>,
+) -> Result<Vec<PicklsDiagnostic>> {
+    let pattern = linter_config
+        .pattern
+        .as_deref()
+        .context("linters with regex output require a pattern")?;
+    let re = Regex::new(pattern).with_context(|| format!("invalid regex [pattern={pattern}]"))?;
+    let multiline = linter_config
+        .multiline
+        .as_ref()
+        .map(MultilinePatterns::new)
+        .transpose()?;
+    let absolute_filename = &uri_to_path(uri);
+    let mut pickls_diagnostics: Vec<PicklsDiagnostic> = Default::default();
+    // The multi-line diagnostic that is still accumulating lines, if any.
+    let mut open_diagnostic: Option<PicklsDiagnostic> = None;
+    let mut prior_line: Option<String> = None;
     for line in child_stdout.lines() {
         let line =
             String::from_utf8_lossy(&strip_ansi_escapes::strip(line?.as_bytes())).to_string();
         log::debug!("line: {line}");
+        if let (Some(multiline), Some(diagnostic)) = (&multiline, open_diagnostic.as_mut()) {
+            let (consumed, ended) =
+                multiline.continue_diagnostic(diagnostic, &line, absolute_filename);
+            if ended {
+                pickls_diagnostics.extend(open_diagnostic.take());
+            }
+            if consumed {
+                prior_line = Some(line);
+                continue;
+            }
+        }
         if let Some(caps) = re.captures(&line) {
             log::trace!("caps: {caps:?}");
-            if let Some(lsp_diagnostic) =
-                convert_capture_to_diagnostic(uri.path().as_str(), linter_config, caps, &prior_line)
+            if let Some(pickls_diagnostic) =
+                convert_capture_to_diagnostic(absolute_filename, linter_config, caps, &prior_line)
             {
-                // log::info!("diagnostic: {lsp_diagnostic:?}");
-                let mut path = std::path::PathBuf::from(lsp_diagnostic.filename.clone());
-                if path.is_relative() {
-                    path = root_dir.join(path);
-                }
-                match path.canonicalize() {
-                    Ok(realpath_for_diagnostic) => {
-                        if realpath_for_uri == realpath_for_diagnostic {
-                            // Note that this filtering can be avoided in cases that the linter is known to
-                            // only lint in the current file. This is intended to filter out diagnostics
-                            // from linters that scan multiple files.
-                            lsp_diagnostics.push(lsp_diagnostic.into());
-                        } else {
-                            log::warn!(
-                                "ignoring diagnostic for {uri} because it is not in the current document [filename={realpath_for_diagnostic:?}]",
-                                uri = uri.as_str(),
-                            );
-                        }
-                    }
-                    Err(e) => {
-                        log::warn!(
-                            "could not canonicalize path {path:?} for diagnostic: {e} cwd={} root_dir={}",
-                            std::env::current_dir()?.display(),
-                            root_dir.display()
-                        );
-                    }
+                if multiline.is_some() {
+                    open_diagnostic = Some(pickls_diagnostic);
+                } else {
+                    pickls_diagnostics.push(pickls_diagnostic);
                 }
             }
         } else {
-            log::trace!(
-                "line did not match linter pattern [pattern={pattern}]",
-                pattern = linter_config.pattern
-            );
+            log::trace!("line did not match linter pattern [pattern={pattern}]");
         }
         prior_line = Some(line);
     }
-    log::info!(
-        "publishing diagnostics [linter={linter_name}, count={count}]",
-        linter_name = linter_config.program,
-        count = lsp_diagnostics.len()
-    );
+    pickls_diagnostics.extend(open_diagnostic);
+    Ok(pickls_diagnostics)
+}
 
-    // TODO: track errors from other documents. For now this is out of reach
-    // because we don't have the current version of the other document
-    // readily available.
-    diagnostics_manager.update_diagnostics(
-        uri.clone(),
-        // Uri::from_file_path(filename.as_str()).unwrap(),
-        linter_config.program.clone(),
-        max_linter_count,
-        version,
-        lsp_diagnostics,
-    )
+#[test]
+fn test_parse_regex_output_multiline() {
+    let linter_config: PicklsLinterConfig = serde_json::from_value(json!({
+        "program": "rustc",
+        "use_stdin": false,
+        "pattern": "^(error|warning)(?:\\[(\\w+)\\])?: (.*)$",
+        "severity_match": 1,
+        "code_match": 2,
+        "description_match": 3,
+        "multiline": {
+            "continuation": "^\\s*\\d*\\s+(?:\\||=) ?(.*)$",
+            "location": {
+                "pattern": "^\\s*(?:-->|:::) ([^:]+):(\\d+):(\\d+)$",
+                "filename_match": 1,
+                "line_match": 2,
+                "start_col_match": 3,
+            },
+        },
+    }))
+    .unwrap();
+    let output = "\
+error[E0308]: mismatched types
+  --> src/main.rs:3:5
+   |
+3  |     x
+   |     ^ expected `u32`
+  ::: src/lib.rs:9:1
+warning: unused variable
+  --> src/main.rs:7:9
+unrelated line
+";
+    let uri = Uri::from_str("file:///src/main.rs").unwrap();
+    let diagnostics =
+        parse_regex_output(&uri, &linter_config, BufReader::new(output.as_bytes())).unwrap();
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].filename, "src/main.rs");
+    assert_eq!(diagnostics[0].line, 3);
+    assert_eq!(diagnostics[0].start_column, Some(5));
+    assert_eq!(diagnostics[0].code.as_deref(), Some("E0308"));
+    assert_eq!(
+        diagnostics[0].description.as_deref(),
+        Some("mismatched types\n    x\n    ^ expected `u32`")
+    );
+    assert_eq!(diagnostics[0].related.len(), 1);
+    assert_eq!(diagnostics[0].related[0].filename, "src/lib.rs");
+    assert_eq!(diagnostics[0].related[0].line, 9);
+    assert_eq!(diagnostics[1].line, 7);
+    assert_eq!(
+        diagnostics[1].description.as_deref(),
+        Some("unused variable")
+    );
 }
 
+/// Runs a formatter on `file_content` and returns the formatted text. If `range` is given and the
+/// formatter has `range_args`, the formatter is asked to format just that range. Otherwise it
+/// formats the whole document.
 pub fn run_formatter(
     formatter_config: &PicklsFormatterConfig,
     workspace: &Workspace,
     file_content: String,
     uri: Uri,
     language_root_markers: &[String],
+    range: Option<Range>,
+    position_encoding: PositionEncoding,
+    cancellation: &CancellationToken,
 ) -> Result<String> {
-    let mut cmd = {
+    let (mut cmd, tmpfile, output, use_stdin) = {
         let filename = uri.path().as_str();
+        let (args, range) = match (range, &formatter_config.range_args) {
+            (Some(range), Some(range_args)) => (range_args, Some(range)),
+            _ => (&formatter_config.args, None),
+        };
 
+        let mentions_tmpfile = args.iter().any(|arg| arg.contains("$tmpfilename"));
+        let output = formatter_config.output.unwrap_or(if mentions_tmpfile {
+            PicklsFormatterOutput::InPlace
+        } else {
+            PicklsFormatterOutput::Stdout
+        });
+        let use_stdin = formatter_config
+            .use_stdin
+            .unwrap_or(output != PicklsFormatterOutput::InPlace);
+        let mut args = args.clone();
+        if output == PicklsFormatterOutput::InPlace && !mentions_tmpfile {
+            args.push("$tmpfilename".to_string());
+        }
+        let tmpfile = create_tmpfile_for_args(
+            &args,
+            filename,
+            &file_content,
+            formatter_config.tmpfile_in_document_dir,
+        )?;
         let mut cmd = Command::new(&formatter_config.program);
-        let mut args = formatter_config.args.clone();
         for arg in args.iter_mut() {
             *arg = arg.replace("$filename", filename);
+            if let Some(tmpfile) = &tmpfile {
+                *arg = arg.replace("$tmpfilename", &tmpfile.path().to_string_lossy());
+            }
+            if let Some(range) = range {
+                *arg = substitute_range_args(arg, &file_content, range, position_encoding);
+            }
         }
         // Resolve effective root markers: use formatter-specific if provided, otherwise inherit from language
         let effective_root_markers = formatter_config
@@ -388,20 +1357,133 @@ pub fn run_formatter(
             program = formatter_config.program
         );
         cmd.process_group(0).args(args).current_dir(root_dir);
-        if formatter_config.use_stdin {
-            cmd.stdin(std::process::Stdio::piped());
-        }
+        // Otherwise the formatter would inherit the server's stdin, which carries the protocol.
+        cmd.stdin(if use_stdin {
+            std::process::Stdio::piped()
+        } else {
+            std::process::Stdio::null()
+        });
         cmd.stdout(std::process::Stdio::piped());
         cmd.stderr(std::process::Stdio::piped());
-        cmd
+        (cmd, tmpfile, output, use_stdin)
     };
 
-    log::info!("spawning {cmd:?} [stdin={}]", formatter_config.use_stdin);
+    log::info!("spawning {cmd:?} [stdin={use_stdin}]");
     let mut child = cmd.spawn()?;
+    let pid = Pid::from_raw(child.id() as i32);
+    let tracked = cancellation.track_process(pid);
+    let watchdog = formatter_config
+        .timeout_ms
+        .map(|timeout_ms| Watchdog::spawn(pid, Duration::from_millis(timeout_ms)));
+    let result = run_formatter_core(
+        formatter_config,
+        output,
+        use_stdin,
+        file_content,
+        &uri,
+        tmpfile.as_ref(),
+        &mut child,
+        || {
+            watchdog.iter().for_each(Watchdog::disarm);
+            drop(tracked);
+        },
+    );
+    cancellation.check()?;
+    if watchdog.is_some_and(|watchdog| watchdog.fired()) {
+        anyhow::bail!(
+            "formatter `{program}` timed out after {timeout_ms}ms",
+            program = formatter_config.program,
+            timeout_ms = formatter_config.timeout_ms.unwrap_or_default()
+        );
+    }
+    result
+}
+
+/// Replaces the range placeholders of `range_args`. Lines are 1-based and inclusive, and bytes are
+/// 0-based offsets with an exclusive end.
+fn substitute_range_args(
+    arg: &str,
+    file_content: &str,
+    range: Range,
+    position_encoding: PositionEncoding,
+) -> String {
+    let start_line = range.start.line + 1;
+    // A selection that ends at the start of a line doesn't include that line.
+    let end_line = if range.end.character == 0 && range.end.line > range.start.line {
+        range.end.line
+    } else {
+        range.end.line + 1
+    };
+    arg.replace("$start_line", &start_line.to_string())
+        .replace("$end_line", &end_line.to_string())
+        .replace(
+            "$start_byte",
+            &position_to_byte_offset(file_content, range.start, position_encoding).to_string(),
+        )
+        .replace(
+            "$end_byte",
+            &position_to_byte_offset(file_content, range.end, position_encoding).to_string(),
+        )
+}
+
+fn run_formatter_core(
+    formatter_config: &PicklsFormatterConfig,
+    output: PicklsFormatterOutput,
+    use_stdin: bool,
+    file_content: String,
+    uri: &Uri,
+    tmpfile: Option<&TmpFile>,
+    child: &mut Child,
+    disarm: impl FnOnce(),
+) -> Result<String> {
+    let formatted_content =
+        read_formatter_output(formatter_config, use_stdin, &file_content, uri, child);
+    if formatted_content.is_err() {
+        // Make sure the formatter doesn't outlive a failure to talk to it. It hasn't been reaped
+        // yet, so its process group can't have been reused.
+        kill_process_group(Pid::from_raw(child.id() as i32));
+    }
+    let exit_status = reap(child, disarm);
+    let formatted_content = formatted_content?;
+    let exit_status = exit_status?;
+    let printed_diff = output == PicklsFormatterOutput::Diff
+        && formatted_content
+            .lines()
+            .any(|line| line.starts_with("@@ -"));
+    if exit_status.success() || printed_diff {
+        match output {
+            PicklsFormatterOutput::Stdout => Ok(formatted_content),
+            PicklsFormatterOutput::Diff => apply_unified_diff(&file_content, &formatted_content)
+                .context("applying the formatter's diff"),
+            PicklsFormatterOutput::InPlace => {
+                let tmpfile = tmpfile.context("in_place formatters require a temp file")?;
+                std::fs::read_to_string(tmpfile.path()).with_context(|| {
+                    format!(
+                        "reading formatted temp file {path:?}",
+                        path = tmpfile.path()
+                    )
+                })
+            }
+        }
+    } else {
+        log::error!("Failed to format file {uri}", uri = uri.as_str());
+        anyhow::bail!("failed to format file {uri:?}", uri = uri.as_str());
+    }
+}
+
+/// Feeds the document to the formatter (if it reads stdin) and reads its output, without waiting
+/// for it to exit.
+fn read_formatter_output(
+    formatter_config: &PicklsFormatterConfig,
+    use_stdin: bool,
+    file_content: &str,
+    uri: &Uri,
+    child: &mut Child,
+) -> Result<String> {
     let mut stdout = child.stdout.take().expect("Failed to open stdout");
     let mut stderr = child.stderr.take().expect("Failed to open stderr");
 
-    if formatter_config.use_stdin {
+    if use_stdin {
         let mut stdin = child.stdin.take().expect("Failed to open stdin");
         stdin.write_all(file_content.as_bytes())?;
     }
@@ -436,12 +1518,28 @@ pub fn run_formatter(
             anyhow::bail!("failed to format file {uri:?}", uri = uri.as_str());
         }
     };
+    Ok(formatted_content)
+}
 
-    let exit_status = child.wait()?;
-    if exit_status.success() {
-        Ok(formatted_content)
-    } else {
-        log::error!("Failed to format file {uri}", uri = uri.as_str());
-        anyhow::bail!("failed to format file {uri:?}", uri = uri.as_str());
-    }
+#[test]
+fn test_run_in_place_formatter() {
+    // The formatter only reads its temp file, so nothing is written to its stdin: a write this
+    // large would block, or fail once the formatter exits.
+    let formatter_config: PicklsFormatterConfig = serde_json::from_value(json!({
+        "program": "sh",
+        "args": ["-c", "sed -i s/a/b/ \"$1\"", "sh", "$tmpfilename"],
+    }))
+    .unwrap();
+    let formatted = run_formatter(
+        &formatter_config,
+        &Workspace::new(),
+        "a\n".repeat(1 << 20),
+        Uri::from_str("file:///tmp/pickls-test-in-place.txt").unwrap(),
+        &[],
+        None,
+        PositionEncoding::Utf16,
+        &CancellationToken::default(),
+    )
+    .unwrap();
+    assert_eq!(formatted, "b\n".repeat(1 << 20));
 }
@@ -2,10 +2,12 @@ use std::{path::Path, process};
 
 use anyhow::{Context, Result};
 use handlebars::Handlebars;
-use lsp_types::Range;
+use lsp_types::{Range, Uri};
 use serde::Serialize;
 pub use sysinfo::{Pid, System};
 
+use crate::{position_encoding::PositionEncoding, text_edits::position_to_byte_offset};
+
 pub fn fetch_parent_process_info() -> String {
     let mut system = System::new_all();
     system.refresh_all();
@@ -24,32 +26,62 @@ pub fn fetch_parent_process_info() -> String {
     "<unknown parent process>".to_string()
 }
 
-/// Returns a slice of the source code based on the given range.
-pub fn slice_range(source: &str, range: Range) -> String {
-    let start_line = range.start.line as i64;
-    let start_character = range.start.character as i64;
-    let end_line = range.end.line as i64;
-    let end_character = range.end.character as i64;
-    let mut line: i64 = 0;
-    let mut character: i64 = 0;
-    let mut slice = String::new();
-    for ch in source.encode_utf16() {
-        if (line > start_line || (line == start_line && character >= start_character))
-            && ((line < end_line) || (line == end_line && character < end_character))
-        {
-            // TODO: PERF reverse the encoding math to find the original slice offset to avoid all
-            // this allocation.
-            let s = char::from_u32(ch.into()).unwrap_or(' ');
-            slice.push(s);
-        }
-        if ch == 10 {
-            line += 1;
-            character = 0;
+/// Returns a slice of the source code based on the given range, whose positions are counted in
+/// `encoding`.
+pub fn slice_range(source: &str, range: Range, encoding: PositionEncoding) -> String {
+    let start = position_to_byte_offset(source, range.start, encoding);
+    let end = position_to_byte_offset(source, range.end, encoding).max(start);
+    source[start..end].to_string()
+}
+
+/// Returns the path of a "file://" URI, with its percent-encoded characters decoded.
+pub fn uri_to_path(uri: &Uri) -> String {
+    percent_decode(uri.path().as_str())
+}
+
+/// Returns the "file://" URI of an absolute path, percent-encoding the characters that can't
+/// appear in a URI as they are.
+pub fn path_to_uri(path: &Path) -> Result<Uri> {
+    use std::os::unix::ffi::OsStrExt as _;
+
+    let mut uri = String::from("file://");
+    for &byte in path.as_os_str().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            uri.push(byte as char);
         } else {
-            character += 1;
+            uri.push_str(&format!("%{byte:02X}"));
         }
     }
-    slice
+    uri.parse()
+        .with_context(|| format!("invalid path {path:?} [uri={uri}]"))
+}
+
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = s
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(byte);
+            i += 3;
+            continue;
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[test]
+fn test_path_to_uri() {
+    let path = Path::new("/src/my project/\u{fc}ber#1.py");
+    let uri = path_to_uri(path).unwrap();
+    assert_eq!(uri.as_str(), "file:///src/my%20project/%C3%BCber%231.py");
+    assert_eq!(uri_to_path(&uri), path.to_str().unwrap());
 }
 
 #[test]
@@ -68,7 +100,8 @@ fn test_slice_range() {
                     line: 1,
                     character: 2
                 }
-            }
+            },
+            PositionEncoding::Utf16
         )
     );
     let source = "fn main() {\n    println!(\"Hello, world!\");\n}\n";
@@ -82,7 +115,30 @@ fn test_slice_range() {
             character: 12,
         },
     };
-    assert_eq!(slice_range(source, range), "println!");
+    assert_eq!(
+        slice_range(source, range, PositionEncoding::Utf16),
+        "println!"
+    );
+    // Characters outside the basic multilingual plane take up two UTF-16 code units.
+    let source = "let s = \"\u{1f600}!\";";
+    let range = Range {
+        start: Position {
+            line: 0,
+            character: 9,
+        },
+        end: Position {
+            line: 0,
+            character: 12,
+        },
+    };
+    assert_eq!(
+        slice_range(source, range, PositionEncoding::Utf16),
+        "\u{1f600}!"
+    );
+    assert_eq!(
+        slice_range(source, range, PositionEncoding::Utf32),
+        "\u{1f600}!\""
+    );
 }
 
 #[allow(dead_code)]